@@ -0,0 +1,333 @@
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf, time::Duration};
+
+use tokio::net::UdpSocket;
+
+use crate::dirs;
+
+/// The SQL Server Browser service listens on this UDP port.
+pub static BROWSER_PORT: u16 = 1434;
+
+static TIMEOUT: Duration = Duration::from_secs(2);
+
+static CACHE_FILE: &str = "instances.json";
+
+/// CLNT_UCAST_INST and SVR_RESP from MS-SQLR.
+static INSTANCE_REQUEST: u8 = 0x04;
+static RESPONSE: u8 = 0x05;
+
+/// Ports of named instances found through the SQL Browser, kept in `~/.tainer` so only the
+/// first connection to an instance has to wait for the lookup.
+pub struct PortCache {
+    path: PathBuf,
+    ports: BTreeMap<String, u16>,
+}
+
+impl PortCache {
+    pub fn load() -> PortCache {
+        PortCache::load_from(dirs::tainer_dir().join(CACHE_FILE))
+    }
+
+    /// A damaged or missing cache only costs a lookup, so it starts over empty.
+    pub fn load_from(path: PathBuf) -> PortCache {
+        let ports = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        PortCache { path, ports }
+    }
+
+    pub fn get(&self, host: &str, instance: &str) -> Option<u16> {
+        self.ports.get(&key(host, instance)).copied()
+    }
+
+    pub fn set(&mut self, host: &str, instance: &str, port: u16) {
+        self.ports.insert(key(host, instance), port);
+    }
+
+    /// Drops a port that no longer works, e.g. after the instance restarted on a dynamic port.
+    pub fn forget(&mut self, host: &str, instance: &str) {
+        self.ports.remove(&key(host, instance));
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(
+            &self.path,
+            serde_json::to_string_pretty(&self.ports).expect("Ports are valid JSON"),
+        )
+        .map_err(|e| format!("Could not write {}: {}", self.path.display(), e))
+    }
+}
+
+fn key(host: &str, instance: &str) -> String {
+    format!("{}\\{}", host, instance).to_lowercase()
+}
+
+/// The port of the named instance on `host`, from the cache or from the SQL Browser on any of
+/// the host's addresses. `browser_port` is only configurable for tests.
+pub async fn resolve(
+    cache: &mut PortCache,
+    host: &str,
+    instance: &str,
+    browser_port: u16,
+) -> Result<u16, String> {
+    if let Some(port) = cache.get(host, instance) {
+        return Ok(port);
+    }
+
+    let addresses = tokio::net::lookup_host((host, browser_port))
+        .await
+        .map_err(|e| format!("Could not resolve {}: {}", host, e))?;
+    let mut last_error = format!("Could not resolve {}", host);
+    for address in addresses {
+        match instance_port(address, instance).await {
+            Ok(port) => {
+                cache.set(host, instance, port);
+                return Ok(port);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Asks the SQL Server Browser at `browser` which TCP port the named instance listens on.
+pub async fn instance_port(browser: SocketAddr, instance: &str) -> Result<u16, String> {
+    let local: SocketAddr = if browser.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(local)
+        .await
+        .map_err(|e| format!("Could not open a UDP socket: {}", e))?;
+
+    let request = [&[INSTANCE_REQUEST], instance.as_bytes(), &[0]].concat();
+    socket
+        .send_to(&request, browser)
+        .await
+        .map_err(|e| format!("Could not reach the SQL Browser at {}: {}", browser, e))?;
+
+    let mut buffer = vec![0u8; 4096];
+    let len = tokio::time::timeout(TIMEOUT, socket.recv(&mut buffer))
+        .await
+        .map_err(|_| {
+            format!(
+                "The SQL Browser at {} did not answer, check that the SQL Server Browser service is running and UDP {} is open in the firewall, or put the port in the connection string (Server=host,port)",
+                browser, BROWSER_PORT
+            )
+        })?
+        // Windows reports an ICMP port unreachable from an earlier send as a failed receive
+        .map_err(|e| format!("The SQL Browser at {} is not reachable: {}", browser, e))?;
+
+    parse_response(&buffer[..len], instance)
+}
+
+/// The response is `0x05`, a little-endian length and `key;value;` pairs ending with `;;`,
+/// e.g. `ServerName;PINKGOLD;InstanceName;PINKGOLD16;IsClustered;No;Version;16.0;tcp;1433;;`.
+fn parse_response(response: &[u8], instance: &str) -> Result<u16, String> {
+    if response.len() < 3 || response[0] != RESPONSE {
+        return Err("The SQL Browser sent an invalid response".to_string());
+    }
+
+    let text = String::from_utf8_lossy(&response[3..]);
+    let fields = text.split(';').collect::<Vec<_>>();
+    let value = |key: &str| {
+        fields
+            .chunks(2)
+            .find(|pair| pair[0].eq_ignore_ascii_case(key))
+            .and_then(|pair| pair.get(1))
+            .map(|value| value.to_string())
+    };
+
+    if !value("InstanceName").is_some_and(|name| name.eq_ignore_ascii_case(instance)) {
+        return Err(format!(
+            "The SQL Browser doesn't know the instance {}, check the instance name",
+            instance
+        ));
+    }
+
+    let port = value("tcp").ok_or_else(|| {
+        format!(
+            "TCP/IP is not enabled for the instance {}, enable it in SQL Server Configuration Manager",
+            instance
+        )
+    })?;
+    port.parse::<u16>()
+        .map_err(|_| format!("The SQL Browser sent an invalid port {}", port))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    /// Answers every request like the SQL Browser on a machine with the given instances.
+    async fn fake_browser(instances: &'static [(&'static str, u16)]) -> (u16, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 256];
+            loop {
+                let (len, from) = socket.recv_from(&mut buffer).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(buffer[0], INSTANCE_REQUEST);
+                let requested = String::from_utf8_lossy(&buffer[1..len])
+                    .trim_end_matches('\0')
+                    .to_string();
+                // like the real browser, unknown instances get no answer at all
+                if let Some((name, tcp_port)) = instances
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&requested))
+                {
+                    socket
+                        .send_to(&response(name, Some(*tcp_port)), from)
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+        (port, requests)
+    }
+
+    fn response(instance: &str, tcp_port: Option<u16>) -> Vec<u8> {
+        let body = format!(
+            "ServerName;PINKGOLD;InstanceName;{};IsClustered;No;Version;16.0.1000.6;{};",
+            instance,
+            tcp_port
+                .map(|port| format!("tcp;{};", port))
+                .unwrap_or_default()
+        );
+        [
+            &[RESPONSE],
+            &(body.len() as u16).to_le_bytes()[..],
+            body.as_bytes(),
+        ]
+        .concat()
+    }
+
+    fn temp_cache(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "tainer-{}-{}-{}",
+            name,
+            std::process::id(),
+            CACHE_FILE
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn asks_the_browser_for_the_instance_port() {
+        let (port, _) = fake_browser(&[("PINKGOLD16", 50123)]).await;
+
+        let found = instance_port(([127, 0, 0, 1], port).into(), "PINKGOLD16").await;
+
+        assert_eq!(found, Ok(50123));
+    }
+
+    #[tokio::test]
+    async fn instance_names_are_case_insensitive() {
+        let (port, _) = fake_browser(&[("PINKGOLD16", 50123)]).await;
+
+        let found = instance_port(([127, 0, 0, 1], port).into(), "pinkgold16").await;
+
+        assert_eq!(found, Ok(50123));
+    }
+
+    #[tokio::test]
+    async fn unknown_instance_times_out_with_advice() {
+        let (port, _) = fake_browser(&[("PINKGOLD16", 50123)]).await;
+
+        let error = instance_port(([127, 0, 0, 1], port).into(), "OTHER")
+            .await
+            .unwrap_err();
+
+        assert!(error.contains("did not answer"), "{}", error);
+        assert!(error.contains("Server=host,port"), "{}", error);
+    }
+
+    #[test]
+    fn reports_instances_without_tcp() {
+        let error = parse_response(&response("PINKGOLD16", None), "PINKGOLD16").unwrap_err();
+
+        assert!(error.contains("TCP/IP is not enabled"), "{}", error);
+    }
+
+    #[test]
+    fn reports_answers_for_another_instance() {
+        let error = parse_response(&response("SQLEXPRESS", Some(1433)), "PINKGOLD16").unwrap_err();
+
+        assert!(error.contains("doesn't know the instance"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_invalid_responses() {
+        assert!(parse_response(&[], "PINKGOLD16").is_err());
+        assert!(parse_response(b"\x04\x00\x00", "PINKGOLD16").is_err());
+        let mut bad_port = response("PINKGOLD16", None);
+        bad_port.extend_from_slice(b"tcp;none;;");
+        assert!(parse_response(&bad_port, "PINKGOLD16").is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_only_asks_the_browser_once() {
+        let (port, requests) = fake_browser(&[("PINKGOLD16", 50123)]).await;
+        let mut cache = PortCache::load_from(temp_cache("once"));
+
+        let first = resolve(&mut cache, "127.0.0.1", "PINKGOLD16", port).await;
+        let second = resolve(&mut cache, "127.0.0.1", "PINKGOLD16", port).await;
+
+        assert_eq!(first, Ok(50123));
+        assert_eq!(second, Ok(50123));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn resolve_asks_again_after_forget() {
+        let (port, requests) = fake_browser(&[("PINKGOLD16", 50123)]).await;
+        let mut cache = PortCache::load_from(temp_cache("forget"));
+        cache.set("127.0.0.1", "PINKGOLD16", 40000);
+
+        cache.forget("127.0.0.1", "PINKGOLD16");
+        let found = resolve(&mut cache, "127.0.0.1", "PINKGOLD16", port).await;
+
+        assert_eq!(found, Ok(50123));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn cache_survives_a_reload() {
+        let path = temp_cache("reload");
+        let mut cache = PortCache::load_from(path.clone());
+        cache.set("PINKGOLD", "PINKGOLD16", 50123);
+        cache.save().unwrap();
+
+        let reloaded = PortCache::load_from(path.clone());
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(reloaded.get("pinkgold", "pinkgold16"), Some(50123));
+        assert_eq!(reloaded.get("pinkgold", "SQLEXPRESS"), None);
+    }
+
+    #[test]
+    fn damaged_cache_starts_empty() {
+        let path = temp_cache("damaged");
+        std::fs::write(&path, "not json").unwrap();
+
+        let cache = PortCache::load_from(path.clone());
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(cache.get("PINKGOLD", "PINKGOLD16"), None);
+    }
+}
@@ -1,7 +1,9 @@
 use std::path::Path;
 
+pub(crate) static APPHOST_PATH: &str = ".vs/Envirotainer.ELOS/config/applicationhost.config";
+
 pub(crate) fn invoke(root_path: &Path) {
-    let apphost_path = root_path.join(".vs/Envirotainer.ELOS/config/applicationhost.config");
+    let apphost_path = root_path.join(APPHOST_PATH);
 
     if !apphost_path.exists() {
         eprintln!("No applicationhost.config found at {:?}", apphost_path);
@@ -1,15 +1,31 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use walkdir::{DirEntry, WalkDir};
 
-use crate::win;
+use crate::{git, platform::Platform, redact::redact, vault};
 
-static CONFIG_FILES: [&str; 3] = ["app.config", "web.config", "appsettings.json"];
+pub(crate) static CONFIG_FILES: [&str; 3] = ["app.config", "web.config", "appsettings.json"];
 
-pub(crate) fn invoke(main: &str, service_bus: &str, root_path: &Path) {
+pub(crate) fn invoke(
+    main: &str,
+    service_bus: &str,
+    protect: bool,
+    root_path: &Path,
+    platform: &dyn Platform,
+) {
     println!("Walking from {}", root_path.display());
-    println!("Setting connection strings to {} and {}", main, service_bus);
-
+    println!(
+        "Setting connection strings to {} and {}",
+        redact(main),
+        redact(service_bus)
+    );
+    // placeholders are resolved here so the secrets are only ever written to the config files
+    let main = &vault::resolve(main).expect("Could not resolve main connection string");
+    let service_bus =
+        &vault::resolve(service_bus).expect("Could not resolve service bus connection string");
+
+    let computer_name = platform.computer_name();
+    let mut patched_configs = Vec::new();
     for entry in WalkDir::new(root_path)
         .into_iter()
         .filter_entry(|e| !is_hidden(e))
@@ -18,10 +34,9 @@ pub(crate) fn invoke(main: &str, service_bus: &str, root_path: &Path) {
         let path = entry.path();
 
         let short_path = path
+            .strip_prefix(root_path)
+            .unwrap_or(path)
             .to_string_lossy()
-            .split_once("ELOS\\")
-            .unwrap()
-            .1
             .to_string();
         match path.extension().and_then(|ext| ext.to_str()) {
             Some("json") => {
@@ -30,12 +45,49 @@ pub(crate) fn invoke(main: &str, service_bus: &str, root_path: &Path) {
             }
             Some("config") => {
                 println!("Appending connection strings for {}", short_path);
-                let computer_name = win::get_computer_name();
-                append_connection_string(path, &computer_name, main, service_bus)
+                append_connection_string(path, &computer_name, main, service_bus);
+                patched_configs.push(path.to_path_buf());
             }
             _ => println!("Unsupported file type!"),
         }
     }
+
+    if protect {
+        protect_configs(&patched_configs, root_path);
+    }
+}
+
+/// Marks the patched, tracked config files as skip-worktree so the machine specific
+/// connection strings don't get committed by accident. `tainer unprotect` reverses it.
+fn protect_configs(paths: &[PathBuf], root_path: &Path) {
+    let protected = paths
+        .iter()
+        .filter(|path| git::is_tracked(path) && git::set_skip_worktree(path, true))
+        .collect::<Vec<_>>();
+    for path in &protected {
+        if !git::record_protected(path) {
+            println!(
+                "⚠️  - could not record {} as protected, `tainer unprotect` will leave it alone",
+                path.strip_prefix(root_path).unwrap_or(path).display()
+            );
+        }
+    }
+
+    if protected.is_empty() {
+        println!("No tracked config files to protect");
+        return;
+    }
+
+    println!(
+        "Protected {} config files from being committed:",
+        protected.len()
+    );
+    for path in protected {
+        println!(
+            "  {}",
+            path.strip_prefix(root_path).unwrap_or(path).display()
+        );
+    }
 }
 
 fn create_dev_appsettings(path: &Path, main: &str, service_bus: &str) {
@@ -130,3 +182,66 @@ fn service_bus_connection_string(computer_name: &str, service_bus: &str) -> Stri
         service_bus = service_bus
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::MockPlatform;
+
+    static WEB_CONFIG: &str = r#"<configuration>
+  <connectionStrings>
+  <add name="PINKGOLD" providerName="System.Data.SqlClient" connectionString="old" />
+  </connectionStrings>
+</configuration>"#;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "tainer-connection-strings-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("Web")).unwrap();
+        root
+    }
+
+    #[test]
+    fn keys_the_connection_strings_by_computer_name() {
+        let root = temp_root("computer-name");
+        std::fs::write(root.join("Web").join("web.config"), WEB_CONFIG).unwrap();
+        let platform = MockPlatform {
+            computer_name: "WSL-BOX".to_string(),
+            ..Default::default()
+        };
+
+        invoke("Server=main", "Server=bus", false, &root, &platform);
+
+        let content = std::fs::read_to_string(root.join("Web").join("web.config")).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(content.contains(r#"<add name="WSL-BOX" providerName="System.Data.SqlClient" connectionString="Server=main" />"#));
+        assert!(content.contains(r#"<add name="WSL-BOX_NSERVICEBUS" providerName="System.Data.SqlClient" connectionString="Server=bus" />"#));
+        assert!(content.contains(
+            r#"<add name="PINKGOLD" providerName="System.Data.SqlClient" connectionString="old" />"#
+        ));
+    }
+
+    #[test]
+    fn replaces_the_connection_strings_of_this_computer() {
+        let root = temp_root("replace");
+        std::fs::write(root.join("Web").join("web.config"), WEB_CONFIG).unwrap();
+        let platform = MockPlatform::default();
+
+        invoke("Server=main", "Server=bus", false, &root, &platform);
+
+        let content = std::fs::read_to_string(root.join("Web").join("web.config")).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(!content.contains(r#"connectionString="old""#));
+        assert_eq!(content.matches(r#"<add name="PINKGOLD" "#).count(), 1);
+        assert_eq!(
+            content
+                .matches(r#"<add name="PINKGOLD_NSERVICEBUS" "#)
+                .count(),
+            1
+        );
+    }
+}
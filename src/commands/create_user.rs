@@ -1,47 +1,299 @@
+use clap::Args;
 use futures_util::TryStreamExt;
 use tiberius::QueryItem;
-use tiberius::{Client, Config};
-use tokio::net::TcpStream;
-use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
 use uuid::Uuid;
 
-static ALL_FEATURES_ROLE_ID: &str = "FE4DA52F-2104-424D-B1C1-B07400E64A44";
+use crate::{
+    commands::roles,
+    config::{self, UserDefaults},
+    db::{self, Connection, SqlClient},
+    redact::redact,
+    schema::{Column, Kind, Table},
+    store::{SqlUserStore, UserStore},
+    timezones::WINDOWS_TIME_ZONES,
+};
 
-pub(crate) async fn invoke(name: &str, email: &str, connection_string: &str) {
+pub(crate) static ALL_FEATURES_ROLE_ID: &str = "FE4DA52F-2104-424D-B1C1-B07400E64A44";
+
+/// The tables and columns creating a user reads and writes.
+pub(crate) static NEW_USER_TABLES: [Table; 3] = [
+    Table {
+        name: "User",
+        columns: &[
+            Column::new("Id", Kind::Guid),
+            Column::new("FirstName", Kind::Text),
+            Column::new("LastName", Kind::Text),
+            Column::new("Email", Kind::Text),
+            Column::new("Username", Kind::Text),
+            Column::new("Timezone", Kind::Text),
+            Column::new("Locale", Kind::Text),
+            Column::new("Active", Kind::Bit),
+            Column::nullable("OfficeId", Kind::GuidOrText),
+            Column::new("SystemUser", Kind::Bit),
+            Column::nullable("DepartmentId", Kind::GuidOrText),
+            Column::nullable("LastLogin", Kind::DateTime),
+            Column::nullable("ActivationChanged", Kind::DateTime),
+        ],
+        inserted: true,
+    },
+    Table {
+        name: "UsersToRoles",
+        columns: &[
+            Column::new("UserId", Kind::GuidOrText),
+            Column::new("UserRoleId", Kind::GuidOrText),
+        ],
+        inserted: true,
+    },
+    Table {
+        name: "UserRole",
+        columns: &[
+            Column::new("Id", Kind::GuidOrText),
+            Column::new("Name", Kind::Text),
+        ],
+        inserted: false,
+    },
+];
+
+/// Surname prefixes that belong to the last name, e.g. "Jan van der Berg".
+static SURNAME_PARTICLES: [&str; 19] = [
+    "af", "al", "av", "bin", "da", "das", "de", "del", "della", "den", "der", "di", "do", "dos",
+    "du", "la", "le", "ten", "van",
+];
+
+/// Overrides for the fields of a new user, the rest come from the config or the name.
+#[derive(Args, Default)]
+pub(crate) struct UserOptions {
+    /// First name, instead of parsing it from the full name
+    #[clap(long)]
+    pub first_name: Option<String>,
+    /// Last name, instead of parsing it from the full name
+    #[clap(long)]
+    pub last_name: Option<String>,
+    /// Username, defaults to the email (or the username template in the config)
+    #[clap(long)]
+    pub username: Option<String>,
+    /// Windows time zone id, e.g. "W. Europe Standard Time"
+    #[clap(long)]
+    pub timezone: Option<String>,
+    /// Locale, e.g. sv-SE
+    #[clap(long)]
+    pub locale: Option<String>,
+    /// Id of the user's office
+    #[clap(long)]
+    pub office_id: Option<String>,
+    /// Id of the user's department
+    #[clap(long)]
+    pub department_id: Option<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct NewUser {
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub username: String,
+    pub timezone: String,
+    pub locale: String,
+    pub office_id: Option<String>,
+    pub department_id: Option<String>,
+}
+
+impl NewUser {
+    /// Combines the name and options with the defaults, and validates the result.
+    pub(crate) fn new(
+        name: &str,
+        email: &str,
+        options: &UserOptions,
+        defaults: &UserDefaults,
+    ) -> Result<NewUser, String> {
+        let (parsed_first_name, parsed_last_name) = parse_name(name);
+        let first_name = options.first_name.clone().unwrap_or(parsed_first_name);
+        let last_name = options.last_name.clone().unwrap_or(parsed_last_name);
+        if first_name.trim().is_empty() {
+            return Err(format!("Missing first name for {}", email));
+        }
+        if !email.contains('@') {
+            return Err(format!("{} is not a valid email", email));
+        }
+
+        let timezone = options
+            .timezone
+            .clone()
+            .unwrap_or_else(|| defaults.timezone.clone());
+        if !WINDOWS_TIME_ZONES.contains(&timezone.as_str()) {
+            let suggestion = WINDOWS_TIME_ZONES
+                .iter()
+                .find(|zone| zone.eq_ignore_ascii_case(&timezone))
+                .map(|zone| format!(", did you mean \"{}\"?", zone))
+                .unwrap_or_else(|| {
+                    ", expected a Windows time zone id such as \"W. Europe Standard Time\""
+                        .to_string()
+                });
+            return Err(format!("Unknown time zone {}{}", timezone, suggestion));
+        }
+
+        let locale = options
+            .locale
+            .clone()
+            .unwrap_or_else(|| defaults.locale.clone());
+        if !is_valid_locale(&locale) {
+            return Err(format!(
+                "Invalid locale {}, expected something like sv-SE or en-US",
+                locale
+            ));
+        }
+
+        Ok(NewUser {
+            first_name: first_name.trim().to_string(),
+            last_name: last_name.trim().to_string(),
+            email: email.to_string(),
+            username: options
+                .username
+                .clone()
+                .unwrap_or_else(|| defaults.username_for(email)),
+            timezone,
+            locale,
+            office_id: options
+                .office_id
+                .clone()
+                .or_else(|| defaults.office_id.clone()),
+            department_id: options
+                .department_id
+                .clone()
+                .or_else(|| defaults.department_id.clone()),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum CreateUserError {
+    Config(String),
+    InvalidUser(String),
+    Secret(String),
+    Connect(tiberius::error::Error),
+    Schema(String),
+    UserExists(String),
+    UnknownRoles(String),
+    Database(tiberius::error::Error),
+}
+
+impl std::fmt::Display for CreateUserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateUserError::Config(e) => write!(f, "{}", e),
+            CreateUserError::InvalidUser(e) => write!(f, "{}", e),
+            CreateUserError::Secret(e) => write!(f, "Could not resolve connection string: {}", e),
+            CreateUserError::Connect(e) => write!(f, "Could not connect to database: {}", e),
+            CreateUserError::Schema(e) => write!(f, "{}\nNothing was changed", e),
+            CreateUserError::UserExists(email) => {
+                write!(f, "User with email {} already exists", email)
+            }
+            CreateUserError::UnknownRoles(e) => write!(f, "{}", e),
+            CreateUserError::Database(e) => {
+                write!(f, "Could not create user, nothing was changed: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CreateUserError {}
+
+impl From<tiberius::error::Error> for CreateUserError {
+    fn from(e: tiberius::error::Error) -> Self {
+        CreateUserError::Database(e)
+    }
+}
+
+/// Creates the user with the given roles (by name), or with `ALL FEATURES` if there are none.
+pub(crate) async fn invoke(
+    name: &str,
+    email: &str,
+    options: &UserOptions,
+    role_names: &[String],
+    connection: &Connection,
+    profile: Option<&str>,
+) -> Result<(), CreateUserError> {
+    let config = config::load().map_err(CreateUserError::Config)?;
+    let user = NewUser::new(name, email, options, &config.user_defaults(profile))
+        .map_err(CreateUserError::InvalidUser)?;
+
+    let mut store = connect(connection).await?;
+    create(&mut store, &user, role_names).await
+}
+
+pub(crate) async fn connect(connection: &Connection) -> Result<SqlUserStore, CreateUserError> {
     println!(
         "Trying to connect to database with connection string: {}",
-        connection_string
+        redact(&connection.connection_string)
     );
-    let mut client = init_client(connection_string)
+    let connection = connection.resolve().map_err(CreateUserError::Secret)?;
+    let client = db::init_client(&connection)
         .await
-        .expect("Could not connect to database");
+        .map_err(CreateUserError::Connect)?;
+    Ok(SqlUserStore::new(client))
+}
+
+/// Everything runs in one transaction, so a failure never leaves a user without roles.
+pub(crate) async fn create(
+    store: &mut impl UserStore,
+    user: &NewUser,
+    role_names: &[String],
+) -> Result<(), CreateUserError> {
+    store.begin_transaction().await?;
+    let result = create_user(store, user, role_names).await;
+    let ended = store.end_transaction(result.is_ok()).await;
+    // the original error is more useful than a failed rollback
+    result?;
+    ended?;
 
-    if user_with_email_exists(&mut client, email)
+    println!(
+        "Created user {} {} with email {}",
+        user.first_name, user.last_name, user.email
+    );
+    Ok(())
+}
+
+async fn create_user(
+    store: &mut impl UserStore,
+    user: &NewUser,
+    role_names: &[String],
+) -> Result<(), CreateUserError> {
+    store
+        .verify_schema(&NEW_USER_TABLES)
         .await
-        .expect("Could not check if user exists")
-    {
-        eprintln!("User with email {} already exists", email);
-        return;
+        .map_err(CreateUserError::Schema)?;
+    if store.user_with_email_exists(&user.email).await? {
+        return Err(CreateUserError::UserExists(user.email.clone()));
     }
 
-    println!("Adding user {} with email {}", name, email);
-    let user_id = insert_user(&mut client, name, email)
-        .await
-        .expect("Could not insert user");
-    println!("Connecting user to role");
-    add_role_to_user(&mut client, &user_id, ALL_FEATURES_ROLE_ID)
-        .await
-        .expect("Could not add role to user");
+    let role_ids = if role_names.is_empty() {
+        vec![ALL_FEATURES_ROLE_ID.to_string()]
+    } else {
+        let roles = store.roles().await?;
+        roles::match_roles(&roles, role_names)
+            .map_err(CreateUserError::UnknownRoles)?
+            .iter()
+            .map(|role| role.id.clone())
+            .collect()
+    };
+
+    println!(
+        "Adding user {} {} with email {}",
+        user.first_name, user.last_name, user.email
+    );
+    let user_id = store.insert_user(user).await?;
+    for role_id in &role_ids {
+        println!("Connecting user to role {}", role_id);
+        store.add_role_to_user(&user_id, role_id).await?;
+    }
 
-    println!("Created user {} with email {}", name, email);
+    Ok(())
 }
 
-async fn insert_user(
-    client: &mut Client<Compat<TcpStream>>,
-    name: &str,
-    email: &str,
+pub(crate) async fn insert_user(
+    client: &mut SqlClient,
+    user: &NewUser,
 ) -> Result<String, tiberius::error::Error> {
-    let (first_name, last_name) = name.split_once(' ').unwrap_or(("", ""));
     let unique_id = Uuid::new_v4();
 
     let query = r#"INSERT INTO [dbo].[User]
@@ -63,33 +315,38 @@ async fn insert_user(
             ,@P2
             ,@P3
             ,@P4
-            ,@P4
-            ,'W. Europe Standard Time'
-            ,'sv-SE'
+            ,@P5
+            ,@P6
+            ,@P7
             ,1
-            ,NULL
+            ,@P8
             ,0
-            ,NULL
+            ,@P9
             ,NULL
             ,NULL)"#;
 
-    match client
-        .execute(query, &[&unique_id, &first_name, &last_name, &email])
-        .await
-    {
-        Ok(_) => {
-            println!("User created successfully");
-            Ok(unique_id.to_string())
-        }
-        Err(e) => {
-            eprintln!("Could not create user: {}", e);
-            Err(e)
-        }
-    }
+    client
+        .execute(
+            query,
+            &[
+                &unique_id,
+                &user.first_name,
+                &user.last_name,
+                &user.email,
+                &user.username,
+                &user.timezone,
+                &user.locale,
+                &user.office_id,
+                &user.department_id,
+            ],
+        )
+        .await?;
+
+    Ok(unique_id.to_string())
 }
 
-async fn add_role_to_user(
-    client: &mut Client<Compat<TcpStream>>,
+pub(crate) async fn add_role_to_user(
+    client: &mut SqlClient,
     user_id: &str,
     role_id: &str,
 ) -> Result<(), tiberius::error::Error> {
@@ -100,20 +357,13 @@ async fn add_role_to_user(
             (@P1
             ,@P2)"#;
 
-    match client.execute(query, &[&user_id, &role_id]).await {
-        Ok(_) => {
-            println!("Role added to user successfully");
-            Ok(())
-        }
-        Err(e) => {
-            eprintln!("Could not add role to user: {}", e);
-            Err(e)
-        }
-    }
+    client.execute(query, &[&user_id, &role_id]).await?;
+
+    Ok(())
 }
 
-async fn user_with_email_exists(
-    client: &mut Client<Compat<TcpStream>>,
+pub(crate) async fn user_with_email_exists(
+    client: &mut SqlClient,
     email: &str,
 ) -> Result<bool, tiberius::error::Error> {
     let query = r#"SELECT COUNT(*)
@@ -125,9 +375,7 @@ async fn user_with_email_exists(
     while let Some(item) = stream.try_next().await? {
         match item {
             QueryItem::Row(row) if row.result_index() == 0 => {
-                row.get::<i32, _>(0)
-                    .map(|count| exists = count > 0)
-                    .expect("Could not get count");
+                exists = row.get::<i32, _>(0).is_some_and(|count| count > 0);
             }
             _ => {}
         }
@@ -136,15 +384,260 @@ async fn user_with_email_exists(
     Ok(exists)
 }
 
-async fn init_client(
-    connection_string: &str,
-) -> Result<Client<Compat<TcpStream>>, tiberius::error::Error> {
-    let config = Config::from_ado_string(connection_string)?;
+/// Splits a full name into first and last name. The last word is the last name, together with
+/// any surname particles before it ("Jan van der Berg"), a single word is only a first name.
+fn parse_name(name: &str) -> (String, String) {
+    let words = name.split_whitespace().collect::<Vec<_>>();
+    if words.len() < 2 {
+        return (words.join(" "), String::new());
+    }
+
+    let mut last_name_start = words.len() - 1;
+    while last_name_start > 1
+        && SURNAME_PARTICLES.contains(&words[last_name_start - 1].to_lowercase().as_str())
+    {
+        last_name_start -= 1;
+    }
+
+    (
+        words[..last_name_start].join(" "),
+        words[last_name_start..].join(" "),
+    )
+}
+
+/// Accepts language[-Script][-REGION], e.g. sv, sv-SE or sr-Latn-RS.
+fn is_valid_locale(locale: &str) -> bool {
+    let mut parts = locale.split('-');
+    let language = parts.next().unwrap_or_default();
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_lowercase()) {
+        return false;
+    }
+
+    let mut rest = parts.collect::<Vec<_>>();
+    if rest.first().is_some_and(|script| {
+        script.len() == 4
+            && script
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_uppercase())
+            && script.chars().skip(1).all(|c| c.is_ascii_lowercase())
+    }) {
+        rest.remove(0);
+    }
+
+    match rest.as_slice() {
+        [] => true,
+        [region] => {
+            (region.len() == 2 && region.chars().all(|c| c.is_ascii_uppercase()))
+                || (region.len() == 3 && region.chars().all(|c| c.is_ascii_digit()))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryUserStore;
+
+    static PLANNER_ROLE_ID: &str = "2B1E4C0D-5A3F-4E8B-9C7D-1F2A3B4C5D6E";
+    static READ_ONLY_ROLE_ID: &str = "7C8D9E0F-1A2B-4C3D-8E4F-5A6B7C8D9E0F";
+
+    fn store() -> InMemoryUserStore {
+        InMemoryUserStore::with_roles(&[
+            (ALL_FEATURES_ROLE_ID, "ALL FEATURES"),
+            (PLANNER_ROLE_ID, "Planner"),
+            (READ_ONLY_ROLE_ID, "Read Only"),
+        ])
+    }
+
+    fn user(email: &str) -> NewUser {
+        NewUser::new(
+            "Pontus Backman",
+            email,
+            &UserOptions::default(),
+            &UserDefaults::default(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn creates_user_with_all_features_by_default() {
+        let mut store = store();
 
-    let tcp = TcpStream::connect(config.get_addr()).await?;
-    tcp.set_nodelay(true)?;
+        create(&mut store, &user("pontus@spinit.se"), &[])
+            .await
+            .unwrap();
+
+        assert_eq!(store.users.len(), 1);
+        assert_eq!(store.users[0].1.first_name, "Pontus");
+        assert_eq!(store.users[0].1.last_name, "Backman");
+        assert_eq!(store.roles_of("pontus@spinit.se"), [ALL_FEATURES_ROLE_ID]);
+        assert_eq!(store.commits, 1);
+    }
+
+    #[tokio::test]
+    async fn gives_the_named_roles() {
+        let mut store = store();
+
+        create(
+            &mut store,
+            &user("planner@spinit.se"),
+            &["planner".to_string(), "Read Only".to_string()],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            store.roles_of("planner@spinit.se"),
+            [PLANNER_ROLE_ID, READ_ONLY_ROLE_ID]
+        );
+    }
 
-    let client = Client::connect(config, tcp.compat_write()).await?;
+    #[tokio::test]
+    async fn unknown_role_changes_nothing() {
+        let mut store = store();
 
-    Ok(client)
+        let result = create(
+            &mut store,
+            &user("planner@spinit.se"),
+            &["Planer".to_string()],
+        )
+        .await;
+
+        match result {
+            Err(CreateUserError::UnknownRoles(e)) => assert!(e.contains("Planner"), "{}", e),
+            _ => panic!("Expected unknown roles"),
+        }
+        assert!(store.users.is_empty());
+        assert_eq!(store.rollbacks, 1);
+    }
+
+    #[tokio::test]
+    async fn existing_email_is_rejected() {
+        let mut store = store();
+        create(&mut store, &user("pontus@spinit.se"), &[])
+            .await
+            .unwrap();
+
+        let result = create(&mut store, &user("pontus@spinit.se"), &[]).await;
+
+        assert!(
+            matches!(result, Err(CreateUserError::UserExists(email)) if email == "pontus@spinit.se")
+        );
+        assert_eq!(store.users.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_to_add_a_role_rolls_back_the_user() {
+        let mut store = store();
+        store.fail_on = Some("add_role_to_user");
+
+        let result = create(&mut store, &user("pontus@spinit.se"), &[]).await;
+
+        assert!(matches!(result, Err(CreateUserError::Database(_))));
+        assert!(store.users.is_empty());
+        assert!(store.user_roles.is_empty());
+        assert_eq!(store.rollbacks, 1);
+    }
+
+    #[tokio::test]
+    async fn schema_mismatch_stops_before_inserting() {
+        let mut store = store();
+        store.schema_error = Some("[dbo].[User] has no column [Locale]".to_string());
+
+        let result = create(&mut store, &user("pontus@spinit.se"), &[]).await;
+
+        assert!(matches!(result, Err(CreateUserError::Schema(_))));
+        assert!(store.users.is_empty());
+    }
+
+    #[test]
+    fn options_override_defaults_and_parsed_name() {
+        let options = UserOptions {
+            first_name: Some("Jan".to_string()),
+            username: Some("jvdb".to_string()),
+            locale: Some("nl-NL".to_string()),
+            ..Default::default()
+        };
+
+        let user = NewUser::new(
+            "Jan van der Berg",
+            "jan@spinit.se",
+            &options,
+            &UserDefaults::default(),
+        )
+        .unwrap();
+
+        assert_eq!(user.first_name, "Jan");
+        assert_eq!(user.last_name, "van der Berg");
+        assert_eq!(user.username, "jvdb");
+        assert_eq!(user.locale, "nl-NL");
+        assert_eq!(user.timezone, "W. Europe Standard Time");
+    }
+
+    #[test]
+    fn rejects_invalid_users() {
+        let defaults = UserDefaults::default();
+        let new_user = |name: &str, email: &str, options: &UserOptions| {
+            NewUser::new(name, email, options, &defaults).map(|_| ())
+        };
+        let timezone = |zone: &str| UserOptions {
+            timezone: Some(zone.to_string()),
+            ..Default::default()
+        };
+        let locale = |locale: &str| UserOptions {
+            locale: Some(locale.to_string()),
+            ..Default::default()
+        };
+
+        assert!(new_user("", "a@spinit.se", &Default::default()).is_err());
+        assert!(new_user("Pontus", "spinit.se", &Default::default()).is_err());
+        assert!(new_user("Pontus", "a@spinit.se", &locale("sv_SE")).is_err());
+        assert!(new_user("Pontus", "a@spinit.se", &timezone("Europe/Stockholm")).is_err());
+        let suggestion = new_user(
+            "Pontus",
+            "a@spinit.se",
+            &timezone("w. europe standard time"),
+        )
+        .unwrap_err();
+        assert!(
+            suggestion.contains("\"W. Europe Standard Time\""),
+            "{}",
+            suggestion
+        );
+    }
+
+    #[test]
+    fn parses_names() {
+        let parse = parse_name;
+
+        assert_eq!(parse("Pontus"), ("Pontus".to_string(), String::new()));
+        assert_eq!(
+            parse("Pontus Backman"),
+            ("Pontus".to_string(), "Backman".to_string())
+        );
+        assert_eq!(
+            parse("Anna Maria Svensson"),
+            ("Anna Maria".to_string(), "Svensson".to_string())
+        );
+        assert_eq!(
+            parse("Jan van der Berg"),
+            ("Jan".to_string(), "van der Berg".to_string())
+        );
+        assert_eq!(
+            parse("  Van  Morrison "),
+            ("Van".to_string(), "Morrison".to_string())
+        );
+    }
+
+    #[test]
+    fn validates_locales() {
+        for locale in ["sv", "sv-SE", "en-US", "sr-Latn-RS", "es-419"] {
+            assert!(is_valid_locale(locale), "{}", locale);
+        }
+        for locale in ["", "SV-se", "sv_SE", "sv-se", "sv-SE-x", "english"] {
+            assert!(!is_valid_locale(locale), "{}", locale);
+        }
+    }
 }
@@ -0,0 +1,576 @@
+use console::style;
+use dialoguer::Confirm;
+use serde_json::Value;
+
+use crate::{
+    db::{self, quote_literal, quote_name, Connection, SqlClient},
+    output::{self, Format},
+    snapshots::{Catalog, Snapshot},
+};
+
+struct ServerInfo {
+    name: String,
+    now: String,
+    backup_path: String,
+    data_path: String,
+    log_path: String,
+    database_exists: bool,
+}
+
+/// A file in a backup, from `RESTORE FILELISTONLY`.
+struct BackupFile {
+    logical_name: String,
+    kind: String,
+}
+
+/// A file of the database being replaced, from `sys.master_files`.
+struct DatabaseFile {
+    logical_name: String,
+    physical_name: String,
+    is_log: bool,
+}
+
+/// Takes a copy-only backup of the database in the connection string, into the server's
+/// default backup directory, and records it in the snapshot catalog under `name`.
+pub(crate) async fn backup(name: &str, force: bool, connection: &Connection) {
+    if let Err(e) = run_backup(name, force, connection).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Replaces the database in the connection string with a snapshot from the catalog, or with
+/// a backup file on the server. Everyone connected to the database is disconnected.
+pub(crate) async fn restore(snapshot_or_file: &str, yes: bool, connection: &Connection) {
+    if let Err(e) = run_restore(snapshot_or_file, yes, connection).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+pub(crate) fn list(format: Format) {
+    let catalog = Catalog::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let rows = catalog
+        .snapshots()
+        .iter()
+        .map(|snapshot| {
+            vec![
+                Value::from(snapshot.name.as_str()),
+                Value::from(snapshot.database.as_str()),
+                Value::from(snapshot.server.as_str()),
+                Value::from(snapshot.created.as_str()),
+                Value::from(snapshot.file.as_str()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    output::print_rows(
+        &["Name", "Database", "Server", "Created", "File"],
+        &rows,
+        format,
+    );
+}
+
+async fn run_backup(name: &str, force: bool, connection: &Connection) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid snapshot name {}, use letters, digits, - and _",
+            name
+        ));
+    }
+
+    let (database, mut client) = connect(connection).await?;
+    let info = server_info(&mut client, &database).await?;
+    if !info.database_exists {
+        return Err(format!(
+            "Database {} does not exist on {}",
+            database, info.name
+        ));
+    }
+
+    let mut catalog = Catalog::load()?;
+    if !force && catalog.find(&info.name, &database, name).is_some() {
+        return Err(format!(
+            "There is already a snapshot named {} of {}, use --force to replace it",
+            name, database
+        ));
+    }
+
+    let file = join_server_path(&info.backup_path, &format!("{}-{}.bak", database, name));
+    println!(
+        "Backing up {} to {}",
+        style(&database).bold(),
+        style(&file).bold()
+    );
+    let query = format!(
+        "BACKUP DATABASE {} TO DISK = {} WITH INIT, COPY_ONLY, NAME = {}",
+        quote_name(&database),
+        quote_literal(&file),
+        quote_literal(&format!("tainer {}", name))
+    );
+    run(&mut client, &query)
+        .await
+        .map_err(|e| format!("Could not back up {}: {}", database, e))?;
+
+    catalog.add(Snapshot {
+        name: name.to_string(),
+        server: info.name,
+        database: database.clone(),
+        file,
+        created: info.now,
+    });
+    catalog.save()?;
+
+    println!("✅ - saved snapshot {} of {}", style(name).bold(), database);
+    Ok(())
+}
+
+async fn run_restore(
+    snapshot_or_file: &str,
+    yes: bool,
+    connection: &Connection,
+) -> Result<(), String> {
+    let (database, mut client) = connect(connection).await?;
+    let info = server_info(&mut client, &database).await?;
+
+    let catalog = Catalog::load()?;
+    let file = match catalog.find(&info.name, &database, snapshot_or_file) {
+        Some(snapshot) => snapshot.file.clone(),
+        None if looks_like_file(snapshot_or_file) => snapshot_or_file.to_string(),
+        None => {
+            let names = catalog
+                .snapshots()
+                .iter()
+                .filter(|snapshot| {
+                    snapshot.server.eq_ignore_ascii_case(&info.name)
+                        && snapshot.database.eq_ignore_ascii_case(&database)
+                })
+                .map(|snapshot| snapshot.name.as_str())
+                .collect::<Vec<_>>();
+            return Err(if names.is_empty() {
+                format!("There are no snapshots of {} on {}", database, info.name)
+            } else {
+                format!(
+                    "No snapshot named {} of {}, available snapshots: {}",
+                    snapshot_or_file,
+                    database,
+                    names.join(", ")
+                )
+            });
+        }
+    };
+
+    if !yes
+        && !Confirm::new()
+            .with_prompt(format!(
+                "Replace {} on {} with {}? Everyone connected to it will be disconnected",
+                database, info.name, file
+            ))
+            .interact()
+            .expect("Failed to get confirmation")
+    {
+        return Ok(());
+    }
+
+    let backup_files = backup_files(&mut client, &file)
+        .await
+        .map_err(|e| format!("Could not read {}: {}", file, e))?;
+    let database_files = database_files(&mut client, &database)
+        .await
+        .map_err(|e| format!("Could not read the files of {}: {}", database, e))?;
+    let moves = moves(&database, &backup_files, &database_files, &info);
+
+    if info.database_exists {
+        println!("Disconnecting everyone from {}", style(&database).bold());
+        run(
+            &mut client,
+            &format!(
+                "ALTER DATABASE {} SET SINGLE_USER WITH ROLLBACK IMMEDIATE",
+                quote_name(&database)
+            ),
+        )
+        .await
+        .map_err(|e| format!("Could not set {} to single user: {}", database, e))?;
+    }
+
+    println!(
+        "Restoring {} from {}",
+        style(&database).bold(),
+        style(&file).bold()
+    );
+    let query = format!(
+        "RESTORE DATABASE {} FROM DISK = {} WITH REPLACE, RECOVERY{}",
+        quote_name(&database),
+        quote_literal(&file),
+        moves
+            .iter()
+            .map(|(logical_name, physical_name)| format!(
+                ", MOVE {} TO {}",
+                quote_literal(logical_name),
+                quote_literal(physical_name)
+            ))
+            .collect::<String>()
+    );
+    let restored = run(&mut client, &query).await;
+
+    // a failed restore would otherwise leave the database locked to this connection
+    if info.database_exists {
+        let reopened = run(
+            &mut client,
+            &format!("ALTER DATABASE {} SET MULTI_USER", quote_name(&database)),
+        )
+        .await;
+        if let Err(e) = reopened {
+            println!(
+                "⚠️  - could not set {} back to multi user: {}",
+                style(&database).bold().yellow(),
+                e
+            );
+        }
+    }
+    restored.map_err(|e| format!("Could not restore {}: {}", database, e))?;
+
+    println!("✅ - restored {} from {}", style(&database).bold(), file);
+    Ok(())
+}
+
+async fn connect(connection: &Connection) -> Result<(String, SqlClient), String> {
+    let connection = connection
+        .resolve()
+        .map_err(|e| format!("Could not resolve connection string: {}", e))?;
+    let database = db::database_name(&connection.connection_string)
+        .ok_or("The connection string has no Initial Catalog or Database")?;
+    let client = db::init_master_client(&connection)
+        .await
+        .map_err(|e| format!("Could not connect to master: {}", e))?;
+    Ok((database, client))
+}
+
+async fn run(client: &mut SqlClient, query: &str) -> Result<(), tiberius::error::Error> {
+    client.simple_query(query).await?.into_results().await?;
+    Ok(())
+}
+
+async fn server_info(client: &mut SqlClient, database: &str) -> Result<ServerInfo, String> {
+    let query = r#"SELECT ISNULL(@@SERVERNAME, '')
+            ,CONVERT(nvarchar(19), SYSDATETIME(), 120)
+            ,CONVERT(nvarchar(4000), SERVERPROPERTY('InstanceDefaultBackupPath'))
+            ,CONVERT(nvarchar(4000), SERVERPROPERTY('InstanceDefaultDataPath'))
+            ,CONVERT(nvarchar(4000), SERVERPROPERTY('InstanceDefaultLogPath'))
+            ,CASE WHEN DB_ID(@P1) IS NULL THEN 0 ELSE 1 END"#;
+
+    let row = client
+        .query(query, &[&database])
+        .await
+        .map_err(|e| format!("Could not read server properties: {}", e))?
+        .into_row()
+        .await
+        .map_err(|e| format!("Could not read server properties: {}", e))?
+        .ok_or("Could not read server properties")?;
+    let text = |i: usize| row.get::<&str, _>(i).unwrap_or_default().to_string();
+
+    Ok(ServerInfo {
+        name: text(0),
+        now: text(1),
+        backup_path: text(2),
+        data_path: text(3),
+        log_path: text(4),
+        database_exists: row.get::<i32, _>(5) == Some(1),
+    })
+}
+
+async fn backup_files(
+    client: &mut SqlClient,
+    file: &str,
+) -> Result<Vec<BackupFile>, tiberius::error::Error> {
+    let rows = client
+        .query("RESTORE FILELISTONLY FROM DISK = @P1", &[&file])
+        .await?
+        .into_first_result()
+        .await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some(BackupFile {
+                logical_name: row.get::<&str, _>("LogicalName")?.to_string(),
+                kind: row.get::<&str, _>("Type")?.to_string(),
+            })
+        })
+        .collect())
+}
+
+async fn database_files(
+    client: &mut SqlClient,
+    database: &str,
+) -> Result<Vec<DatabaseFile>, tiberius::error::Error> {
+    let query = r#"SELECT [name]
+            ,[physical_name]
+            ,[type]
+        FROM sys.master_files
+        WHERE [database_id] = DB_ID(@P1)"#;
+
+    let rows = client
+        .query(query, &[&database])
+        .await?
+        .into_first_result()
+        .await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some(DatabaseFile {
+                logical_name: row.get::<&str, _>(0)?.to_string(),
+                physical_name: row.get::<&str, _>(1)?.to_string(),
+                is_log: row.get::<u8, _>(2)? == 1,
+            })
+        })
+        .collect())
+}
+
+/// Where each file in the backup goes. Files that the current database also has keep their
+/// location, the rest are named after the database, so restoring a backup of another database
+/// never overwrites that database's files.
+fn moves(
+    database: &str,
+    backup_files: &[BackupFile],
+    database_files: &[DatabaseFile],
+    info: &ServerInfo,
+) -> Vec<(String, String)> {
+    let directory_of = |is_log: bool| {
+        database_files
+            .iter()
+            .find(|file| file.is_log == is_log)
+            .and_then(|file| {
+                file.physical_name
+                    .rfind(['\\', '/'])
+                    .map(|i| file.physical_name[..i].to_string())
+            })
+            .unwrap_or_else(|| {
+                if is_log {
+                    info.log_path.clone()
+                } else {
+                    info.data_path.clone()
+                }
+            })
+    };
+
+    let mut has_primary = false;
+    backup_files
+        .iter()
+        .map(|file| {
+            let is_first_data_file = file.kind == "D" && !has_primary;
+            has_primary |= file.kind == "D";
+            if let Some(existing) = database_files.iter().find(|existing| {
+                existing
+                    .logical_name
+                    .eq_ignore_ascii_case(&file.logical_name)
+            }) {
+                return (file.logical_name.clone(), existing.physical_name.clone());
+            }
+
+            let is_log = file.kind == "L";
+            let extension = match file.kind.as_str() {
+                "L" => ".ldf",
+                "D" if is_first_data_file => ".mdf",
+                "D" => ".ndf",
+                // filestream and full-text containers are directories
+                _ => "",
+            };
+            let name = format!("{}_{}{}", database, file.logical_name, extension);
+            (
+                file.logical_name.clone(),
+                join_server_path(&directory_of(is_log), &name),
+            )
+        })
+        .collect()
+}
+
+/// Joins paths on the database server, which isn't necessarily the same OS as this machine.
+fn join_server_path(directory: &str, name: &str) -> String {
+    if directory.is_empty() || directory.ends_with(['\\', '/']) {
+        return format!("{}{}", directory, name);
+    }
+
+    let separator = if directory.contains('/') && !directory.contains('\\') {
+        '/'
+    } else {
+        '\\'
+    };
+    format!("{}{}{}", directory, separator, name)
+}
+
+fn looks_like_file(text: &str) -> bool {
+    text.to_lowercase().ends_with(".bak") || text.contains(['\\', '/'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(data_path: &str, log_path: &str) -> ServerInfo {
+        ServerInfo {
+            name: "PINKGOLD".to_string(),
+            now: "2026-10-19 12:00:00".to_string(),
+            backup_path: String::new(),
+            data_path: data_path.to_string(),
+            log_path: log_path.to_string(),
+            database_exists: true,
+        }
+    }
+
+    fn backup_file(logical_name: &str, kind: &str) -> BackupFile {
+        BackupFile {
+            logical_name: logical_name.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+
+    fn database_file(logical_name: &str, physical_name: &str, is_log: bool) -> DatabaseFile {
+        DatabaseFile {
+            logical_name: logical_name.to_string(),
+            physical_name: physical_name.to_string(),
+            is_log,
+        }
+    }
+
+    fn pairs(moves: &[(String, String)]) -> Vec<(&str, &str)> {
+        moves
+            .iter()
+            .map(|(logical, physical)| (logical.as_str(), physical.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn joins_with_the_separator_of_the_server() {
+        assert_eq!(
+            join_server_path("D:\\SQL\\Data", "Elos.mdf"),
+            "D:\\SQL\\Data\\Elos.mdf"
+        );
+        assert_eq!(
+            join_server_path("D:\\SQL\\Data\\", "Elos.mdf"),
+            "D:\\SQL\\Data\\Elos.mdf"
+        );
+        assert_eq!(
+            join_server_path("/var/opt/mssql/data", "Elos.mdf"),
+            "/var/opt/mssql/data/Elos.mdf"
+        );
+        assert_eq!(
+            join_server_path("/var/opt/mssql/data/", "Elos.mdf"),
+            "/var/opt/mssql/data/Elos.mdf"
+        );
+        assert_eq!(join_server_path("", "Elos.mdf"), "Elos.mdf");
+    }
+
+    #[test]
+    fn files_the_database_has_keep_their_location() {
+        let backup = [backup_file("Elos", "D"), backup_file("Elos_log", "L")];
+        let existing = [
+            database_file("elos", "D:\\Data\\Elos.mdf", false),
+            database_file("ELOS_LOG", "E:\\Logs\\Elos_log.ldf", true),
+        ];
+
+        let moves = moves(
+            "Elos",
+            &backup,
+            &existing,
+            &info("C:\\Default", "C:\\Default"),
+        );
+
+        assert_eq!(
+            pairs(&moves),
+            [
+                ("Elos", "D:\\Data\\Elos.mdf"),
+                ("Elos_log", "E:\\Logs\\Elos_log.ldf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_files_are_named_after_the_database_next_to_its_files() {
+        let backup = [
+            backup_file("Customer", "D"),
+            backup_file("Customer_2", "D"),
+            backup_file("Customer_log", "L"),
+        ];
+        let existing = [
+            database_file("Elos", "D:\\Data\\Elos.mdf", false),
+            database_file("Elos_log", "E:\\Logs\\Elos_log.ldf", true),
+        ];
+
+        let moves = moves(
+            "Elos",
+            &backup,
+            &existing,
+            &info("C:\\Default", "C:\\Default"),
+        );
+
+        assert_eq!(
+            pairs(&moves),
+            [
+                ("Customer", "D:\\Data\\Elos_Customer.mdf"),
+                ("Customer_2", "D:\\Data\\Elos_Customer_2.ndf"),
+                ("Customer_log", "E:\\Logs\\Elos_Customer_log.ldf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn new_databases_go_to_the_default_paths_of_a_linux_server() {
+        let backup = [
+            backup_file("Customer", "D"),
+            backup_file("Customer_log", "L"),
+            backup_file("Customer_fs", "S"),
+        ];
+
+        let moves = moves(
+            "Elos",
+            &backup,
+            &[],
+            &info("/var/opt/mssql/data/", "/var/opt/mssql/log"),
+        );
+
+        assert_eq!(
+            pairs(&moves),
+            [
+                ("Customer", "/var/opt/mssql/data/Elos_Customer.mdf"),
+                ("Customer_log", "/var/opt/mssql/log/Elos_Customer_log.ldf"),
+                ("Customer_fs", "/var/opt/mssql/data/Elos_Customer_fs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_first_data_file_is_the_primary_one_even_if_it_exists() {
+        let backup = [
+            backup_file("Elos", "D"),
+            backup_file("Elos_archive", "D"),
+            backup_file("Elos_log", "L"),
+        ];
+        let existing = [
+            database_file("Elos", "D:\\Data\\Elos.mdf", false),
+            database_file("Elos_log", "E:\\Logs\\Elos_log.ldf", true),
+        ];
+
+        let moves = moves(
+            "Elos",
+            &backup,
+            &existing,
+            &info("C:\\Default", "C:\\Default"),
+        );
+
+        assert_eq!(
+            pairs(&moves),
+            [
+                ("Elos", "D:\\Data\\Elos.mdf"),
+                ("Elos_archive", "D:\\Data\\Elos_Elos_archive.ndf"),
+                ("Elos_log", "E:\\Logs\\Elos_log.ldf"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use console::style;
+use tiberius::Client;
+use tokio::net::TcpStream;
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+
+use crate::{
+    browser,
+    db::{self, Connection, SqlClient},
+    redact::redact,
+};
+
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+static LOGIN_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Connects one stage at a time and reports how long each took, stopping at the first stage
+/// that fails with what to check. The TLS handshake and the login are one combined stage,
+/// since tiberius does both inside `Client::connect` and can't time the handshake on its own.
+pub(crate) async fn invoke(connection: &Connection) {
+    println!("Pinging {}", redact(&connection.connection_string));
+    if run(connection).await.is_err() {
+        std::process::exit(1);
+    }
+}
+
+async fn run(connection: &Connection) -> Result<(), ()> {
+    let (config, server, database) = stage("Connection string", async {
+        let connection = connection
+            .resolve()
+            .map_err(|e| format!("Could not resolve connection string: {}", e))?;
+        let mut config = db::parse_config(&connection)?;
+        let server = db::server(&connection.connection_string)?;
+        let database = db::database_name(&connection.connection_string);
+        // log in to master first, so a missing database is reported as its own stage
+        config.database("master");
+        let login = db::configure_login(&mut config, &connection)?;
+
+        let detail = format!(
+            "server {}{}, database {}, {}",
+            server.host,
+            server
+                .instance
+                .as_ref()
+                .map(|instance| format!(", instance {}", instance))
+                .unwrap_or_default(),
+            database.as_deref().unwrap_or("(default)"),
+            login
+        );
+        Ok(((config, server, database), detail))
+    })
+    .await?;
+
+    let addresses = stage("DNS", async {
+        let addresses = tokio::net::lookup_host((server.host.as_str(), 0))
+            .await
+            .map_err(|e| {
+                format!(
+                    "Could not resolve {}: {}, check the server name or use its IP address",
+                    server.host, e
+                )
+            })?
+            .collect::<Vec<_>>();
+        let detail = addresses
+            .iter()
+            .map(|address| address.ip().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok((addresses, detail))
+    })
+    .await?;
+
+    let port = match (server.port, &server.instance) {
+        (Some(port), _) => {
+            skip("SQL Browser", "port given in the connection string");
+            port
+        }
+        (None, None) => {
+            skip(
+                "SQL Browser",
+                "no instance name, using the default port 1433",
+            );
+            1433
+        }
+        (None, Some(instance)) => {
+            stage("SQL Browser", async {
+                let mut last_error = String::new();
+                for address in &addresses {
+                    let browser = SocketAddr::new(address.ip(), browser::BROWSER_PORT);
+                    match browser::instance_port(browser, instance).await {
+                        Ok(port) => return Ok((port, format!("{} is on port {}", instance, port))),
+                        Err(e) => last_error = e,
+                    }
+                }
+                Err(last_error)
+            })
+            .await?
+        }
+    };
+
+    let tcp = stage("TCP connect", async {
+        let mut last_error = String::new();
+        for address in &addresses {
+            let address = SocketAddr::new(address.ip(), port);
+            match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+                Ok(Ok(tcp)) => return Ok((tcp, address.to_string())),
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    last_error = format!(
+                        "Nothing is listening on {}, check that SQL Server is running and that TCP/IP is enabled in SQL Server Configuration Manager",
+                        address
+                    )
+                }
+                Ok(Err(e)) => last_error = format!("Could not connect to {}: {}", address, e),
+                Err(_) => {
+                    last_error = format!(
+                        "Timed out connecting to {} after {}s, check the firewall on the server",
+                        address,
+                        CONNECT_TIMEOUT.as_secs()
+                    )
+                }
+            }
+        }
+        Err(last_error)
+    })
+    .await?;
+
+    // tiberius negotiates TLS inside the login handshake, so the two are timed together and
+    // told apart by the error
+    let mut client = stage("TLS + login (combined)", async {
+        tcp.set_nodelay(true)
+            .map_err(|e| format!("Could not configure the connection: {}", e))?;
+        let login = Client::connect(config, tcp.compat_write());
+        let Ok(client) = tokio::time::timeout(LOGIN_TIMEOUT, login).await else {
+            return Err(format!(
+                "The server accepted the connection but did not finish the login within {}s",
+                LOGIN_TIMEOUT.as_secs()
+            ));
+        };
+        let client = client.map_err(login_error)?;
+        Ok((client, "logged in to master".to_string()))
+    })
+    .await?;
+
+    match database {
+        Some(database) => {
+            stage("Database access", async {
+                use_database(&mut client, &database).await?;
+                Ok(((), format!("opened {}", database)))
+            })
+            .await?
+        }
+        None => skip("Database access", "no database in the connection string"),
+    }
+
+    Ok(())
+}
+
+async fn use_database(client: &mut SqlClient, database: &str) -> Result<(), String> {
+    let query = format!("USE {}", db::quote_name(database));
+    let result = match client.simple_query(query).await {
+        Ok(stream) => stream.into_results().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+
+    result.map_err(|e| match &e {
+        tiberius::error::Error::Server(token) if token.code() == 911 => format!(
+            "Database {} does not exist, check the Initial Catalog (tainer setup creates it)",
+            database
+        ),
+        tiberius::error::Error::Server(token) if token.code() == 916 || token.code() == 4060 => {
+            format!(
+                "The login has no access to {}, map it to a database user: {}",
+                database,
+                token.message()
+            )
+        }
+        _ => format!("Could not open {}: {}", database, e),
+    })
+}
+
+fn login_error(e: tiberius::error::Error) -> String {
+    match &e {
+        tiberius::error::Error::Tls(message) => db::describe_tls_error(message),
+        tiberius::error::Error::Server(token) if token.code() == 18456 => format!(
+            "Login failed, check the user id and password, and that the server allows SQL Server authentication: {}",
+            token.message()
+        ),
+        tiberius::error::Error::Server(token) if token.code() == 18452 => format!(
+            "The login is from an untrusted domain, integrated security is not available here: {}",
+            token.message()
+        ),
+        tiberius::error::Error::Routing { host, port } => format!(
+            "The server redirected the connection to {}:{}, connect to that address instead",
+            host, port
+        ),
+        tiberius::error::Error::Io { message, .. } => format!(
+            "The connection broke during the login ({}), check that the port belongs to SQL Server and that Encrypt matches the server's settings",
+            message
+        ),
+        _ => format!("Could not log in: {}", e),
+    }
+}
+
+async fn stage<T>(
+    name: &str,
+    work: impl Future<Output = Result<(T, String), String>>,
+) -> Result<T, ()> {
+    let start = Instant::now();
+    let result = work.await;
+    let elapsed = start.elapsed().as_millis();
+    match result {
+        Ok((value, detail)) => {
+            println!(
+                "✅ - {} ({} ms) {}",
+                style(name).bold(),
+                elapsed,
+                style(detail).dim()
+            );
+            Ok(value)
+        }
+        Err(e) => {
+            println!("❌ - {} ({} ms) {}", style(name).bold().red(), elapsed, e);
+            Err(())
+        }
+    }
+}
+
+fn skip(name: &str, reason: &str) {
+    println!("➖ - {} {}", style(name).bold(), style(reason).dim());
+}
@@ -0,0 +1,300 @@
+use std::{collections::VecDeque, path::PathBuf};
+
+use console::style;
+use dialoguer::{History, Input};
+use futures_util::TryStreamExt;
+use serde_json::Value;
+use tiberius::{
+    time::chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime},
+    ColumnData, FromSql, QueryItem,
+};
+
+use crate::{
+    commands::{db_seed, or_exit},
+    db::{self, Connection, SqlClient},
+    dirs,
+    output::{self, Format},
+};
+
+static HISTORY_FILE: &str = "db_history";
+static MAX_HISTORY: usize = 500;
+
+struct ResultSet {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+pub(crate) async fn query(sql: &str, format: Format, connection: &Connection) {
+    let mut client = connect(connection).await;
+    match run_batch(&mut client, sql).await {
+        Ok(result_sets) => print_result_sets(&result_sets, format),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Interactive console, statements run when a line ends with `;` or on a `GO` line, unless the
+/// `;` or `GO` is inside a string or comment.
+pub(crate) async fn shell(format: Format, connection: &Connection) {
+    let mut client = connect(connection).await;
+    let mut history = FileHistory::load(dirs::tainer_dir().join(HISTORY_FILE));
+    println!(
+        "Connected, end statements with {} or {}, and leave with {}",
+        style(";").bold(),
+        style("GO").bold(),
+        style("exit").bold()
+    );
+
+    let mut batch = String::new();
+    loop {
+        let prompt = if batch.is_empty() { "sql" } else { "  …" };
+        let Ok(line) = Input::<String>::new()
+            .with_prompt(prompt)
+            .allow_empty(true)
+            .history_with(&mut history)
+            .interact_text()
+        else {
+            break;
+        };
+
+        let trimmed = line.trim();
+        if batch.is_empty() && (trimmed == "exit" || trimmed == "quit") {
+            break;
+        }
+
+        batch.push_str(&line);
+        batch.push('\n');
+        let Some(sql) = complete_batch(&batch) else {
+            continue;
+        };
+        if !sql.trim().is_empty() {
+            match run_batch(&mut client, &sql).await {
+                Ok(result_sets) => print_result_sets(&result_sets, format),
+                Err(e) => eprintln!("{}", style(e).red()),
+            }
+        }
+        batch.clear();
+    }
+}
+
+/// The statements to run if the last line of `batch` ends it, with a `;` or a `GO` line that
+/// isn't inside a string or comment. `None` while the batch goes on.
+fn complete_batch(batch: &str) -> Option<String> {
+    let code = db_seed::strip_strings_and_comments(batch);
+    if code
+        .lines()
+        .last()
+        .is_some_and(|line| line.trim().eq_ignore_ascii_case("go"))
+    {
+        let without_go = batch.trim_end_matches('\n');
+        let end = without_go.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        return Some(batch[..end].to_string());
+    }
+    code.trim_end().ends_with(';').then(|| batch.to_string())
+}
+
+async fn connect(connection: &Connection) -> SqlClient {
+    let connection = or_exit(connection.resolve(), "Could not resolve connection string");
+    or_exit(
+        db::init_client(&connection).await,
+        "Could not connect to database",
+    )
+}
+
+async fn run_batch(
+    client: &mut SqlClient,
+    sql: &str,
+) -> Result<Vec<ResultSet>, tiberius::error::Error> {
+    let mut stream = client.simple_query(sql).await?;
+    let mut result_sets = Vec::new();
+    while let Some(item) = stream.try_next().await? {
+        match item {
+            QueryItem::Metadata(metadata) => result_sets.push(ResultSet {
+                columns: unique_column_names(
+                    &metadata
+                        .columns()
+                        .iter()
+                        .map(|column| column.name())
+                        .collect::<Vec<_>>(),
+                ),
+                rows: Vec::new(),
+            }),
+            QueryItem::Row(row) => {
+                if let Some(result_set) = result_sets.last_mut() {
+                    result_set
+                        .rows
+                        .push(row.into_iter().map(|data| to_value(&data)).collect());
+                }
+            }
+        }
+    }
+
+    Ok(result_sets)
+}
+
+/// Names every column uniquely, so the JSON objects keep every value: unnamed columns get
+/// their position (`col1`) and repeated names a number (`Id_2`).
+fn unique_column_names(names: &[&str]) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::with_capacity(names.len());
+    for (i, name) in names.iter().enumerate() {
+        let base = if name.is_empty() {
+            format!("col{}", i + 1)
+        } else {
+            name.to_string()
+        };
+        let mut column = base.clone();
+        let mut n = 1;
+        while unique.contains(&column) {
+            n += 1;
+            column = format!("{}_{}", base, n);
+        }
+        unique.push(column);
+    }
+    unique
+}
+
+fn print_result_sets(result_sets: &[ResultSet], format: Format) {
+    if result_sets.is_empty() {
+        println!("Done, no rows returned");
+        return;
+    }
+
+    for (i, result_set) in result_sets.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let columns = result_set
+            .columns
+            .iter()
+            .map(|column| column.as_str())
+            .collect::<Vec<_>>();
+        output::print_rows(&columns, &result_set.rows, format);
+        if matches!(format, Format::Table) {
+            println!("({} rows)", result_set.rows.len());
+        }
+    }
+}
+
+fn to_value(data: &ColumnData<'static>) -> Value {
+    let value = match data {
+        ColumnData::U8(value) => value.map(Value::from),
+        ColumnData::I16(value) => value.map(Value::from),
+        ColumnData::I32(value) => value.map(Value::from),
+        ColumnData::I64(value) => value.map(Value::from),
+        ColumnData::F32(value) => value.map(Value::from),
+        ColumnData::F64(value) => value.map(Value::from),
+        ColumnData::Bit(value) => value.map(Value::from),
+        ColumnData::String(value) => value.as_ref().map(|s| Value::from(s.as_ref())),
+        ColumnData::Guid(value) => value.map(|guid| Value::from(guid.to_string().to_uppercase())),
+        ColumnData::Binary(value) => value.as_ref().map(|bytes| {
+            let hex = bytes
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<String>();
+            Value::from(format!("0x{}", hex))
+        }),
+        ColumnData::Numeric(value) => value.map(|numeric| Value::from(numeric.to_string())),
+        ColumnData::Xml(value) => value.as_ref().map(|xml| Value::from(xml.to_string())),
+        ColumnData::DateTime(_) | ColumnData::SmallDateTime(_) | ColumnData::DateTime2(_) => {
+            NaiveDateTime::from_sql(data)
+                .ok()
+                .flatten()
+                .map(|datetime| Value::from(datetime.to_string()))
+        }
+        ColumnData::Date(_) => NaiveDate::from_sql(data)
+            .ok()
+            .flatten()
+            .map(|date| Value::from(date.to_string())),
+        ColumnData::Time(_) => NaiveTime::from_sql(data)
+            .ok()
+            .flatten()
+            .map(|time| Value::from(time.to_string())),
+        ColumnData::DateTimeOffset(_) => DateTime::<FixedOffset>::from_sql(data)
+            .ok()
+            .flatten()
+            .map(|datetime| Value::from(datetime.to_rfc3339())),
+    };
+
+    value.unwrap_or(Value::Null)
+}
+
+/// Line history for the shell, kept in `~/.tainer` between sessions.
+struct FileHistory {
+    path: PathBuf,
+    entries: VecDeque<String>,
+}
+
+impl FileHistory {
+    fn load(path: PathBuf) -> FileHistory {
+        let entries = std::fs::read_to_string(&path)
+            .map(|content| content.lines().rev().map(|line| line.to_string()).collect())
+            .unwrap_or_default();
+        FileHistory { path, entries }
+    }
+}
+
+impl History<String> for FileHistory {
+    fn read(&self, pos: usize) -> Option<String> {
+        self.entries.get(pos).cloned()
+    }
+
+    fn write(&mut self, val: &String) {
+        if val.trim().is_empty() || self.entries.front() == Some(val) {
+            return;
+        }
+
+        self.entries.push_front(val.clone());
+        self.entries.truncate(MAX_HISTORY);
+        let content = self
+            .entries
+            .iter()
+            .rev()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n");
+        // losing the history isn't worth interrupting the shell for
+        let _ = std::fs::create_dir_all(self.path.parent().expect("History path has a parent"));
+        let _ = std::fs::write(&self.path, content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_unnamed_and_repeated_columns() {
+        assert_eq!(
+            unique_column_names(&["", "", "Id", "Name", "Id", "Id_2"]),
+            ["col1", "col2", "Id", "Name", "Id_2", "Id_2_2"]
+        );
+    }
+
+    #[test]
+    fn runs_on_a_semicolon_or_go_line() {
+        assert_eq!(
+            complete_batch("SELECT 1;\n").as_deref(),
+            Some("SELECT 1;\n")
+        );
+        assert_eq!(
+            complete_batch("SELECT 1\ngo\n").as_deref(),
+            Some("SELECT 1\n")
+        );
+        assert_eq!(complete_batch("GO\n").as_deref(), Some(""));
+        assert_eq!(complete_batch("SELECT 1\n"), None);
+    }
+
+    #[test]
+    fn keeps_reading_inside_strings_and_comments() {
+        assert_eq!(complete_batch("SELECT 'a;\n"), None);
+        assert_eq!(complete_batch("SELECT 'a;\nGO\n"), None);
+        assert_eq!(complete_batch("SELECT 1 -- done;\n"), None);
+        assert_eq!(complete_batch("SELECT 1 /* done;\n"), None);
+        assert_eq!(
+            complete_batch("SELECT 'a;\nb';\n").as_deref(),
+            Some("SELECT 'a;\nb';\n")
+        );
+    }
+}
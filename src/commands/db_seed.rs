@@ -0,0 +1,432 @@
+use std::path::{Path, PathBuf};
+
+use console::style;
+
+use crate::{
+    commands::or_exit,
+    db::{self, Connection, SqlClient},
+};
+
+static HISTORY_TABLE: &str = "[dbo].[__TainerSeedHistory]";
+
+/// Statements SQL Server refuses to run inside a transaction, as pairs of leading keywords.
+static NON_TRANSACTIONAL: [(&str, &str); 8] = [
+    ("CREATE", "DATABASE"),
+    ("ALTER", "DATABASE"),
+    ("DROP", "DATABASE"),
+    ("CREATE", "FULLTEXT"),
+    ("ALTER", "FULLTEXT"),
+    ("DROP", "FULLTEXT"),
+    ("BACKUP", ""),
+    ("RESTORE", ""),
+];
+
+pub(crate) async fn invoke(dir: &Path, reset: bool, transactions: bool, connection: &Connection) {
+    let connection = or_exit(connection.resolve(), "Could not resolve connection string");
+    let mut client = or_exit(
+        db::init_client(&connection).await,
+        "Could not connect to database",
+    );
+
+    if let Err(e) = run_scripts(&mut client, dir, reset, transactions).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the `.sql` files in `dir` in file name order, skipping the ones already recorded in
+/// the history table unless `reset` is set. Stops at the first failing script.
+///
+/// With `transactions` each script runs in its own transaction, except scripts with
+/// statements SQL Server doesn't allow in one (like `CREATE DATABASE`).
+pub(crate) async fn run_scripts(
+    client: &mut SqlClient,
+    dir: &Path,
+    reset: bool,
+    transactions: bool,
+) -> Result<(), String> {
+    let scripts = find_scripts(dir)?;
+    if scripts.is_empty() {
+        println!("No .sql files found in {}", dir.display());
+        return Ok(());
+    }
+
+    ensure_history_table(client)
+        .await
+        .map_err(|e| format!("Could not create {}: {}", HISTORY_TABLE, e))?;
+    let history = applied_scripts(client)
+        .await
+        .map_err(|e| format!("Could not read {}: {}", HISTORY_TABLE, e))?;
+
+    for path in scripts {
+        let name = path
+            .file_name()
+            .expect("Script has a file name")
+            .to_string_lossy()
+            .to_string();
+        let content = read_script(&path)?;
+        let checksum = checksum(&content);
+
+        if let Some((_, applied_checksum)) = history.iter().find(|(script, _)| *script == name) {
+            if !reset {
+                if *applied_checksum != checksum {
+                    println!(
+                        "⚠️  - {} has changed since it was applied, run with --reset to rerun it",
+                        style(&name).bold().yellow()
+                    );
+                }
+                continue;
+            }
+        }
+
+        let batches = split_batches(&content);
+        let transaction = transactions && !needs_autocommit(&batches);
+        if transactions && !transaction {
+            println!(
+                "⚠️  - {} has statements that can't run in a transaction, running it without one",
+                style(&name).bold().yellow()
+            );
+        }
+        if let Err(e) = apply_script(client, &batches, &name, &checksum, transaction).await {
+            println!("❌ - failed to apply {}", style(&name).bold().red());
+            return Err(e);
+        }
+        println!(
+            "✅ - applied {} ({} batches)",
+            style(&name).bold(),
+            batches.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs the script, in a transaction if `transaction` is set. A failed rollback is reported
+/// along with the error that caused it.
+async fn apply_script(
+    client: &mut SqlClient,
+    batches: &[String],
+    name: &str,
+    checksum: &str,
+    transaction: bool,
+) -> Result<(), String> {
+    let failed = |batch: usize, e: &tiberius::error::Error| {
+        if batch == batches.len() {
+            format!("Could not record {} in {}: {}", name, HISTORY_TABLE, e)
+        } else {
+            format!("Batch {} of {} failed: {}", batch + 1, name, e)
+        }
+    };
+
+    if !transaction {
+        return run_script(client, batches, name, checksum)
+            .await
+            .map_err(|(batch, e)| {
+                format!(
+                    "{}\nThe script ran without a transaction, batches before it were not rolled back",
+                    failed(batch, &e)
+                )
+            });
+    }
+
+    db::begin_transaction(client)
+        .await
+        .map_err(|e| format!("Could not begin transaction: {}", e))?;
+    let result = run_script(client, batches, name, checksum).await;
+    let ended = db::end_transaction(client, result.is_ok()).await;
+    match (result, ended) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Ok(()), Err(e)) => Err(format!("Could not commit {}: {}", name, e)),
+        (Err((batch, e)), Ok(())) => {
+            Err(format!("{}\nThe script was rolled back", failed(batch, &e)))
+        }
+        (Err((batch, e)), Err(rollback)) => Err(format!(
+            "{}\nThe rollback failed as well: {}",
+            failed(batch, &e),
+            rollback
+        )),
+    }
+}
+
+async fn run_script(
+    client: &mut SqlClient,
+    batches: &[String],
+    name: &str,
+    checksum: &str,
+) -> Result<(), (usize, tiberius::error::Error)> {
+    for (i, batch) in batches.iter().enumerate() {
+        client
+            .simple_query(batch.as_str())
+            .await
+            .map_err(|e| (i, e))?
+            .into_results()
+            .await
+            .map_err(|e| (i, e))?;
+    }
+
+    let query = format!(
+        r#"MERGE {HISTORY_TABLE} AS history
+        USING (SELECT @P1 AS [Script], @P2 AS [Checksum]) AS script
+        ON history.[Script] = script.[Script]
+        WHEN MATCHED THEN
+            UPDATE SET [Checksum] = script.[Checksum], [AppliedAt] = SYSUTCDATETIME()
+        WHEN NOT MATCHED THEN
+            INSERT ([Script], [Checksum]) VALUES (script.[Script], script.[Checksum]);"#
+    );
+    client
+        .execute(query, &[&name, &checksum])
+        .await
+        .map_err(|e| (batches.len(), e))?;
+
+    Ok(())
+}
+
+async fn ensure_history_table(client: &mut SqlClient) -> Result<(), tiberius::error::Error> {
+    let query = format!(
+        r#"IF OBJECT_ID(N'{HISTORY_TABLE}', N'U') IS NULL
+        CREATE TABLE {HISTORY_TABLE}
+            ([Script] nvarchar(260) NOT NULL PRIMARY KEY
+            ,[Checksum] nvarchar(16) NOT NULL
+            ,[AppliedAt] datetime2 NOT NULL DEFAULT SYSUTCDATETIME())"#
+    );
+    client.simple_query(query).await?.into_results().await?;
+    Ok(())
+}
+
+async fn applied_scripts(
+    client: &mut SqlClient,
+) -> Result<Vec<(String, String)>, tiberius::error::Error> {
+    let query = format!("SELECT [Script], [Checksum] FROM {HISTORY_TABLE}");
+    let rows = client.query(query, &[]).await?.into_first_result().await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some((
+                row.get::<&str, _>(0)?.to_string(),
+                row.get::<&str, _>(1)?.to_string(),
+            ))
+        })
+        .collect())
+}
+
+fn find_scripts(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+
+    let mut scripts = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("sql"))
+        })
+        .collect::<Vec<_>>();
+    scripts.sort();
+    Ok(scripts)
+}
+
+/// Reads a script saved as UTF-8 or as UTF-16 (which SSMS likes to use).
+fn read_script(path: &Path) -> Result<String, String> {
+    let bytes =
+        std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+    if let Some(utf16) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        let units = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect::<Vec<_>>();
+        return String::from_utf16(&units)
+            .map_err(|e| format!("{} is not valid UTF-16: {}", path.display(), e));
+    }
+
+    String::from_utf8(bytes)
+        .map(|content| content.trim_start_matches('\u{feff}').to_string())
+        .map_err(|e| format!("{} is not valid UTF-8: {}", path.display(), e))
+}
+
+/// Splits a script on `GO` lines like SSMS and sqlcmd do, `GO <count>` repeats the batch.
+/// `GO` inside a string or a comment doesn't count.
+fn split_batches(content: &str) -> Vec<String> {
+    let code = strip_strings_and_comments(content);
+    let mut batches = Vec::new();
+    let mut batch = String::new();
+    for (line, code_line) in content.lines().zip(code.lines()) {
+        let trimmed = code_line.trim();
+        let count = if trimmed.eq_ignore_ascii_case("go") {
+            Some(1)
+        } else {
+            trimmed
+                .get(..3)
+                .filter(|go| go.eq_ignore_ascii_case("go "))
+                .and_then(|_| trimmed[3..].trim().parse::<usize>().ok())
+        };
+
+        match count {
+            Some(count) => {
+                if !batch.trim().is_empty() {
+                    for _ in 0..count {
+                        batches.push(batch.clone());
+                    }
+                }
+                batch.clear();
+            }
+            None => {
+                batch.push_str(line);
+                batch.push('\n');
+            }
+        }
+    }
+    if !batch.trim().is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+/// Whether any batch starts a statement from [`NON_TRANSACTIONAL`].
+fn needs_autocommit(batches: &[String]) -> bool {
+    batches.iter().any(|batch| {
+        let code = strip_strings_and_comments(batch).to_ascii_uppercase();
+        let words = code
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        words.iter().enumerate().any(|(i, word)| {
+            NON_TRANSACTIONAL.iter().any(|(first, second)| {
+                word == first
+                    && (second.is_empty() || words.get(i + 1).is_some_and(|next| next == second))
+            })
+        })
+    })
+}
+
+/// Replaces strings, quoted identifiers and comments with spaces, keeping the line breaks so
+/// the lines still line up with the original.
+pub(crate) fn strip_strings_and_comments(sql: &str) -> String {
+    let mut code = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let blank = |c: char| if c == '\n' { '\n' } else { ' ' };
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '[' | '"' => {
+                let end = if c == '[' { ']' } else { c };
+                code.push(' ');
+                while let Some(c) = chars.next() {
+                    code.push(blank(c));
+                    // a doubled closing character is an escaped one
+                    if c == end
+                        && chars
+                            .next_if_eq(&end)
+                            .map(|c| code.push(blank(c)))
+                            .is_none()
+                    {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                code.push(' ');
+                while let Some(c) = chars.next_if(|c| *c != '\n') {
+                    code.push(blank(c));
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                // block comments nest in T-SQL
+                let mut depth = 0;
+                let mut previous = c;
+                code.push(' ');
+                for c in chars.by_ref() {
+                    code.push(blank(c));
+                    match (previous, c) {
+                        ('/', '*') => depth += 1,
+                        ('*', '/') => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    // "*/*" closes a comment, it doesn't also open one
+                    previous = if matches!((previous, c), ('/', '*') | ('*', '/')) {
+                        ' '
+                    } else {
+                        c
+                    };
+                }
+            }
+            c => code.push(c),
+        }
+    }
+    code
+}
+
+/// FNV-1a, only used to notice scripts that changed after they were applied.
+fn checksum(content: &str) -> String {
+    let hash = content.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_go_lines_in_any_case() {
+        let batches =
+            split_batches("CREATE TABLE a (x int)\nGO\n  go  \nINSERT INTO a VALUES (1)\nGo\n");
+
+        assert_eq!(
+            batches,
+            ["CREATE TABLE a (x int)\n", "INSERT INTO a VALUES (1)\n"]
+        );
+    }
+
+    #[test]
+    fn repeats_a_batch_with_a_go_count() {
+        let batches = split_batches("INSERT INTO a DEFAULT VALUES\nGO 3\nSELECT 1\n");
+
+        assert_eq!(batches.len(), 4);
+        assert_eq!(batches[2], "INSERT INTO a DEFAULT VALUES\n");
+        assert_eq!(batches[3], "SELECT 1\n");
+    }
+
+    #[test]
+    fn ignores_go_in_strings_and_comments() {
+        let script = "INSERT INTO a VALUES ('first\nGO\nit''s')\n/* block\nGO\n/* nested */\nGO\n*/\nSELECT 1 -- GO\nGO -- end of the first batch\nSELECT 2\n";
+
+        let batches = split_batches(script);
+
+        assert_eq!(batches.len(), 2, "{:#?}", batches);
+        assert!(batches[0].starts_with("INSERT INTO a VALUES ('first\nGO\nit''s')\n"));
+        assert!(batches[0].ends_with("SELECT 1 -- GO\n"));
+        assert_eq!(batches[1], "SELECT 2\n");
+    }
+
+    #[test]
+    fn ignores_scripts_without_go() {
+        assert_eq!(split_batches("SELECT 1"), ["SELECT 1\n"]);
+        assert!(split_batches("GO\n\nGO\n").is_empty());
+    }
+
+    #[test]
+    fn finds_statements_that_cannot_run_in_a_transaction() {
+        let batches = |sql: &str| split_batches(sql);
+
+        assert!(needs_autocommit(&batches("create database [Elos]")));
+        assert!(needs_autocommit(&batches(
+            "SELECT 1\nGO\nALTER DATABASE CURRENT SET RECOVERY SIMPLE"
+        )));
+        assert!(needs_autocommit(&batches(
+            "CREATE FULLTEXT CATALOG ft AS DEFAULT"
+        )));
+        assert!(!needs_autocommit(&batches(
+            "-- CREATE DATABASE x\nINSERT INTO [Create Database] VALUES ('ALTER DATABASE')"
+        )));
+        assert!(!needs_autocommit(&batches(
+            "CREATE TABLE RestoreLog (x int)"
+        )));
+    }
+}
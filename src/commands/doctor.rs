@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use console::style;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{git, platform::Platform};
+
+pub(crate) fn invoke(root_path: &Path, platform: &dyn Platform) {
+    let computer_name = platform.computer_name();
+    let git_modules = git::find_git_modules(root_path);
+
+    let mut warnings = git_modules
+        .par_iter()
+        .flat_map(|module| {
+            git::staged_additions(module)
+                .into_iter()
+                .filter(|(_, line)| is_machine_connection_string(line, &computer_name))
+                .map(|(file, _)| (module.name.clone(), file))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    warnings.sort();
+    warnings.dedup();
+
+    if warnings.is_empty() {
+        println!("✅ - no machine specific connection strings are staged");
+        return;
+    }
+
+    for (module, file) in &warnings {
+        println!(
+            "⚠️  - staged changes to {} in {} contain the connection strings for {}",
+            style(file).bold(),
+            style(module).bold().yellow(),
+            style(&computer_name).bold()
+        );
+    }
+    println!(
+        "Unstage them with {}, and run {} to keep them out of future commits",
+        style("git restore --staged <file>").bold().dim(),
+        style("tainer connection-strings --protect").bold().dim()
+    );
+}
+
+fn is_machine_connection_string(line: &str, computer_name: &str) -> bool {
+    line.contains(&format!(r#"name="{}""#, computer_name))
+        || line.contains(&format!(r#"name="{}_NSERVICEBUS""#, computer_name))
+}
@@ -0,0 +1,259 @@
+use std::path::{Path, PathBuf};
+
+use console::style;
+use dialoguer::Confirm;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::git::{self, find_git_modules, GitModule};
+
+pub(crate) static DEFAULT_HOOKS_DIR: &str = ".githooks";
+
+struct HookTarget<'a> {
+    module: &'a GitModule,
+    source: PathBuf,
+    destination: PathBuf,
+}
+
+pub(crate) fn install(hooks_dir: &Path, symlink: bool, dry_run: bool, yes: bool, root_path: &Path) {
+    let hooks = find_hooks(&root_path.join(hooks_dir));
+    if hooks.is_empty() {
+        eprintln!("No hooks found in {}", root_path.join(hooks_dir).display());
+        return;
+    }
+
+    let git_modules = find_git_modules(root_path);
+    let targets = skip_sources(hook_targets(&git_modules, &hooks));
+
+    let overwritten = targets
+        .iter()
+        .filter(|target| target.destination.exists() && !is_installed(target))
+        .collect::<Vec<_>>();
+    if !overwritten.is_empty() {
+        println!("The following hooks will be overwritten:");
+        for target in &overwritten {
+            println!(
+                "  {} in {}",
+                style(file_name(&target.destination)).bold(),
+                style(&target.module.name).bold()
+            );
+        }
+    }
+    if dry_run {
+        println!(
+            "Would install {} hooks in {} repositories",
+            hooks.len(),
+            git_modules.len()
+        );
+        return;
+    }
+    if !overwritten.is_empty()
+        && !yes
+        && !Confirm::new()
+            .with_prompt("Overwrite these hooks?")
+            .interact()
+            .expect("Failed to get confirmation")
+    {
+        return;
+    }
+
+    targets
+        .par_iter()
+        .for_each(|target| match install_hook(target, symlink) {
+            Ok(_) => println!(
+                "✅ - installed {} in {}",
+                style(file_name(&target.destination)).bold().dim(),
+                style(&target.module.name).bold()
+            ),
+            Err(e) => println!(
+                "❌ - failed to install {} in {}: {}",
+                style(file_name(&target.destination)).bold().dim(),
+                style(&target.module.name).bold().red(),
+                e
+            ),
+        });
+}
+
+pub(crate) fn uninstall(hooks_dir: &Path, root_path: &Path) {
+    let hooks = find_hooks(&root_path.join(hooks_dir));
+    if hooks.is_empty() {
+        eprintln!("No hooks found in {}", root_path.join(hooks_dir).display());
+        return;
+    }
+
+    let git_modules = find_git_modules(root_path);
+    let targets = skip_sources(hook_targets(&git_modules, &hooks));
+    targets.par_iter().for_each(|target| {
+        if !target.destination.exists() && !target.destination.is_symlink() {
+            return;
+        }
+        // leave hooks that someone changed or installed from elsewhere alone
+        if !is_installed(target) {
+            println!(
+                "⚠️  - skipped {} in {}, it differs from {}",
+                style(file_name(&target.destination)).bold().dim(),
+                style(&target.module.name).bold().yellow(),
+                target.source.display()
+            );
+            return;
+        }
+
+        match std::fs::remove_file(&target.destination) {
+            Ok(_) => println!(
+                "✅ - removed {} from {}",
+                style(file_name(&target.destination)).bold().dim(),
+                style(&target.module.name).bold()
+            ),
+            Err(e) => println!(
+                "❌ - failed to remove {} from {}: {}",
+                style(file_name(&target.destination)).bold().dim(),
+                style(&target.module.name).bold().red(),
+                e
+            ),
+        }
+    });
+}
+
+fn find_hooks(hooks_dir: &Path) -> Vec<PathBuf> {
+    // absolute paths, symlinks are resolved relative to the hooks directory they live in
+    let Ok(entries) = std::fs::canonicalize(hooks_dir).and_then(std::fs::read_dir) else {
+        return Vec::new();
+    };
+
+    let mut hooks = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    hooks.sort();
+    hooks
+}
+
+fn hook_targets<'a>(git_modules: &'a [GitModule], hooks: &[PathBuf]) -> Vec<HookTarget<'a>> {
+    git_modules
+        .iter()
+        .filter_map(|module| match git::hooks_dir(module) {
+            Some(hooks_dir) => Some((module, hooks_dir)),
+            None => {
+                println!(
+                    "❌ - could not find the hooks directory of {}",
+                    style(&module.name).bold().red()
+                );
+                None
+            }
+        })
+        .flat_map(|(module, hooks_dir)| {
+            hooks.iter().map(move |hook| HookTarget {
+                module,
+                source: hook.clone(),
+                destination: hooks_dir.join(hook.file_name().expect("Hook has no file name")),
+            })
+        })
+        .collect()
+}
+
+/// Leaves out the targets whose destination is the source hook itself, which happens when
+/// `core.hooksPath` points at the shared hooks directory. Replacing those would delete them.
+fn skip_sources(targets: Vec<HookTarget>) -> Vec<HookTarget> {
+    targets
+        .into_iter()
+        .filter(|target| {
+            if !is_source(target) {
+                return true;
+            }
+            println!(
+                "⚠️  - skipped {} in {}, its hooks directory is {}",
+                style(file_name(&target.destination)).bold().dim(),
+                style(&target.module.name).bold().yellow(),
+                target.source.parent().unwrap_or(&target.source).display()
+            );
+            false
+        })
+        .collect()
+}
+
+/// Compares the resolved directories rather than the files, so a symlink to the source is not
+/// the source.
+fn is_source(target: &HookTarget) -> bool {
+    let (Some(dir), Some(name)) = (target.destination.parent(), target.destination.file_name())
+    else {
+        return false;
+    };
+    std::fs::canonicalize(dir).is_ok_and(|dir| dir.join(name) == target.source)
+}
+
+/// Whether the destination already is the source hook, either as a symlink or as a copy.
+fn is_installed(target: &HookTarget) -> bool {
+    if let Ok(link) = std::fs::read_link(&target.destination) {
+        return link == target.source;
+    }
+
+    match (
+        std::fs::read(&target.source),
+        std::fs::read(&target.destination),
+    ) {
+        (Ok(source), Ok(destination)) => source == destination,
+        _ => false,
+    }
+}
+
+fn install_hook(target: &HookTarget, symlink: bool) -> std::io::Result<()> {
+    if let Some(hooks_dir) = target.destination.parent() {
+        std::fs::create_dir_all(hooks_dir)?;
+    }
+    if target.destination.exists() || target.destination.is_symlink() {
+        std::fs::remove_file(&target.destination)?;
+    }
+
+    if symlink {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&target.source, &target.destination)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(&target.source, &target.destination)?;
+    } else {
+        // fs::copy keeps the permissions, so executable hooks stay executable
+        std::fs::copy(&target.source, &target.destination)?;
+    }
+
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("Failed to run git command")
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn leaves_the_shared_hooks_alone_when_core_hooks_path_points_at_them() {
+        let root = std::env::temp_dir().join(format!("tainer-hooks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let hooks_dir = root.join(DEFAULT_HOOKS_DIR);
+        std::fs::create_dir_all(&hooks_dir).unwrap();
+        std::fs::write(hooks_dir.join("pre-commit"), "#!/bin/sh\nexit 0\n").unwrap();
+        git(&root, &["init", "--quiet"]);
+        git(&root, &["config", "core.hooksPath", DEFAULT_HOOKS_DIR]);
+
+        install(Path::new(DEFAULT_HOOKS_DIR), false, false, true, &root);
+        let after_install = std::fs::read_to_string(hooks_dir.join("pre-commit"));
+        uninstall(Path::new(DEFAULT_HOOKS_DIR), &root);
+        let after_uninstall = std::fs::read_to_string(hooks_dir.join("pre-commit"));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(after_install.unwrap(), "#!/bin/sh\nexit 0\n");
+        assert_eq!(after_uninstall.unwrap(), "#!/bin/sh\nexit 0\n");
+    }
+}
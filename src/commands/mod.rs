@@ -1,7 +1,29 @@
 pub(crate) mod application_host;
 pub(crate) mod connection_strings;
 pub(crate) mod create_user;
+pub(crate) mod db_backup;
+pub(crate) mod db_ping;
+pub(crate) mod db_query;
+pub(crate) mod db_seed;
+pub(crate) mod doctor;
 pub(crate) mod git_cmd;
+pub(crate) mod hooks;
+pub(crate) mod queues;
+pub(crate) mod roles;
+pub(crate) mod secret;
 pub(crate) mod setup;
+pub(crate) mod stash;
+pub(crate) mod unprotect;
+pub(crate) mod user;
+pub(crate) mod user_import;
 pub(crate) mod watch;
 pub(crate) mod web_api;
+pub(crate) mod worktree;
+
+/// The value, or prints the error and exits with 1, for commands that can't go on without it.
+pub(crate) fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>, context: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}: {}", context, e);
+        std::process::exit(1);
+    })
+}
@@ -0,0 +1,396 @@
+use std::path::Path;
+
+use console::style;
+use dialoguer::Confirm;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    output::{self, Format},
+    platform::{Platform, Queue},
+};
+
+pub(crate) static DEFAULT_MANIFEST: &str = "queues.toml";
+
+/// The queues ELOS expects, e.g.
+///
+/// ```toml
+/// [[queue]]
+/// name = "elos.notifications"
+///
+/// [[queue]]
+/// name = "elos.audit.errors"
+/// transactional = false
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Manifest {
+    #[serde(default, rename = "queue")]
+    pub queues: Vec<ExpectedQueue>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ExpectedQueue {
+    pub name: String,
+    /// NServiceBus needs transactional queues, so that is the default
+    #[serde(default = "transactional")]
+    pub transactional: bool,
+}
+
+fn transactional() -> bool {
+    true
+}
+
+impl Manifest {
+    pub(crate) fn load(path: &Path) -> Result<Manifest, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        toml::from_str(&content)
+            .map_err(|e| format!("Invalid queue manifest {}: {}", path.display(), e))
+    }
+}
+
+/// How the queues on the machine differ from the manifest.
+#[derive(Debug, PartialEq)]
+enum Difference<'a> {
+    Missing(&'a ExpectedQueue),
+    Unexpected(&'a Queue),
+    Transactional { name: &'a str, expected: bool },
+}
+
+/// MSMQ queue names are case insensitive.
+fn find<'a>(queues: &'a [Queue], name: &str) -> Option<&'a Queue> {
+    queues
+        .iter()
+        .find(|queue| queue.name.eq_ignore_ascii_case(name))
+}
+
+fn differences<'a>(manifest: &'a Manifest, queues: &'a [Queue]) -> Vec<Difference<'a>> {
+    let mut differences = Vec::new();
+    for expected in &manifest.queues {
+        match find(queues, &expected.name) {
+            None => differences.push(Difference::Missing(expected)),
+            Some(queue) if queue.transactional != expected.transactional => {
+                differences.push(Difference::Transactional {
+                    name: &queue.name,
+                    expected: expected.transactional,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for queue in queues {
+        if !manifest
+            .queues
+            .iter()
+            .any(|expected| expected.name.eq_ignore_ascii_case(&queue.name))
+        {
+            differences.push(Difference::Unexpected(queue));
+        }
+    }
+    differences
+}
+
+fn kind(transactional: bool) -> &'static str {
+    if transactional {
+        "transactional"
+    } else {
+        "non-transactional"
+    }
+}
+
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+pub(crate) fn list(platform: &dyn Platform, format: Format) {
+    let queues = platform.queues().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let rows = queues
+        .iter()
+        .map(|queue| {
+            vec![
+                Value::from(queue.name.as_str()),
+                Value::from(queue.transactional),
+                Value::from(queue.messages),
+            ]
+        })
+        .collect::<Vec<_>>();
+    output::print_rows(&["Name", "Transactional", "Messages"], &rows, format);
+}
+
+pub(crate) fn create(platform: &dyn Platform, manifest_path: &Path) {
+    exit_on_error(
+        Manifest::load(manifest_path).and_then(|manifest| create_queues(platform, &manifest)),
+    );
+}
+
+/// Creates the queues in the manifest that don't exist. Existing queues are never recreated,
+/// since that would lose their messages.
+pub(crate) fn create_queues(platform: &dyn Platform, manifest: &Manifest) -> Result<(), String> {
+    let queues = platform.queues()?;
+    let mut failed = 0;
+    for expected in &manifest.queues {
+        match find(&queues, &expected.name) {
+            Some(queue) if queue.transactional != expected.transactional => println!(
+                "⚠️  - {} exists but is {}, delete it to recreate it as {}",
+                style(&queue.name).bold(),
+                kind(queue.transactional),
+                kind(expected.transactional)
+            ),
+            Some(queue) => println!("{} already exists", style(&queue.name).bold()),
+            None => match platform.create_queue(&expected.name, expected.transactional) {
+                Ok(()) => println!("✅ - created {}", style(&expected.name).bold()),
+                Err(e) => {
+                    println!(
+                        "❌ - could not create {}: {}",
+                        style(&expected.name).bold(),
+                        e
+                    );
+                    failed += 1;
+                }
+            },
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("Failed to create {} queue(s)", failed));
+    }
+    Ok(())
+}
+
+pub(crate) fn purge(platform: &dyn Platform, name: &str, yes: bool) {
+    exit_on_error(purge_queue(platform, name, yes));
+}
+
+fn purge_queue(platform: &dyn Platform, name: &str, yes: bool) -> Result<(), String> {
+    let queues = platform.queues()?;
+    let queue = find(&queues, name).ok_or_else(|| format!("No queue named {}", name))?;
+
+    if !yes
+        && queue.messages > 0
+        && !Confirm::new()
+            .with_prompt(format!(
+                "Remove the {} message(s) in {}?",
+                queue.messages, queue.name
+            ))
+            .interact()
+            .expect("Failed to get confirmation")
+    {
+        return Ok(());
+    }
+
+    platform.purge_queue(&queue.name)?;
+    println!("✅ - purged {}", style(&queue.name).bold());
+    Ok(())
+}
+
+pub(crate) fn delete(platform: &dyn Platform, name: &str, yes: bool) {
+    exit_on_error(delete_queue(platform, name, yes));
+}
+
+fn delete_queue(platform: &dyn Platform, name: &str, yes: bool) -> Result<(), String> {
+    let queues = platform.queues()?;
+    let queue = find(&queues, name).ok_or_else(|| format!("No queue named {}", name))?;
+
+    if !yes
+        && !Confirm::new()
+            .with_prompt(format!(
+                "Delete {} and the {} message(s) in it?",
+                queue.name, queue.messages
+            ))
+            .interact()
+            .expect("Failed to get confirmation")
+    {
+        return Ok(());
+    }
+
+    platform.delete_queue(&queue.name)?;
+    println!("✅ - deleted {}", style(&queue.name).bold());
+    Ok(())
+}
+
+pub(crate) fn grant(platform: &dyn Platform, account: &str, manifest_path: &Path) {
+    exit_on_error(
+        Manifest::load(manifest_path)
+            .and_then(|manifest| grant_access(platform, account, &manifest)),
+    );
+}
+
+/// Gives `account` full control of every queue in the manifest that exists.
+pub(crate) fn grant_access(
+    platform: &dyn Platform,
+    account: &str,
+    manifest: &Manifest,
+) -> Result<(), String> {
+    let queues = platform.queues()?;
+    let mut failed = 0;
+    for expected in &manifest.queues {
+        let Some(queue) = find(&queues, &expected.name) else {
+            println!(
+                "⚠️  - {} does not exist, create it with `queues create`",
+                style(&expected.name).bold()
+            );
+            continue;
+        };
+        match platform.grant_queue_access(&queue.name, account) {
+            Ok(()) => println!(
+                "✅ - gave {} full control of {}",
+                account,
+                style(&queue.name).bold()
+            ),
+            Err(e) => {
+                println!("❌ - could not change {}: {}", style(&queue.name).bold(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("Failed to change {} queue(s)", failed));
+    }
+    Ok(())
+}
+
+/// Exits with 1 if the queues differ from the manifest, so it can be used in scripts.
+pub(crate) fn diff(platform: &dyn Platform, manifest_path: &Path) {
+    let result = Manifest::load(manifest_path).and_then(|manifest| {
+        let queues = platform.queues()?;
+        let differences = differences(&manifest, &queues);
+        for difference in &differences {
+            match difference {
+                Difference::Missing(expected) => println!(
+                    "{} {} is missing",
+                    style("+").green(),
+                    style(&expected.name).bold()
+                ),
+                Difference::Unexpected(queue) => println!(
+                    "{} {} is not in the manifest",
+                    style("-").red(),
+                    style(&queue.name).bold()
+                ),
+                Difference::Transactional { name, expected } => println!(
+                    "{} {} should be {}",
+                    style("~").yellow(),
+                    style(name).bold(),
+                    kind(*expected)
+                ),
+            }
+        }
+        Ok(differences.len())
+    });
+
+    match result {
+        Ok(0) => println!("✅ - the queues match {}", manifest_path.display()),
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::MockPlatform;
+
+    fn manifest(content: &str) -> Manifest {
+        toml::from_str(content).unwrap()
+    }
+
+    static MANIFEST: &str = r#"
+        [[queue]]
+        name = "elos.notifications"
+
+        [[queue]]
+        name = "elos.audit"
+        transactional = false
+    "#;
+
+    #[test]
+    fn queues_are_transactional_by_default() {
+        let manifest = manifest(MANIFEST);
+
+        assert!(manifest.queues[0].transactional);
+        assert!(!manifest.queues[1].transactional);
+    }
+
+    #[test]
+    fn creates_only_the_missing_queues() {
+        let platform = MockPlatform::with_queues(&["ELOS.Notifications"]);
+
+        create_queues(&platform, &manifest(MANIFEST)).unwrap();
+
+        assert_eq!(*platform.calls.borrow(), ["create_queue elos.audit"]);
+        let queues = platform.queues.borrow();
+        assert_eq!(queues[1].name, "elos.audit");
+        assert!(!queues[1].transactional);
+    }
+
+    #[test]
+    fn leaves_queues_of_the_wrong_kind_alone() {
+        let platform = MockPlatform::with_queues(&["elos.notifications", "elos.audit"]);
+
+        create_queues(&platform, &manifest(MANIFEST)).unwrap();
+
+        assert!(platform.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn diffs_against_the_manifest() {
+        let platform = MockPlatform::with_queues(&["elos.audit", "elos.legacy"]);
+        let manifest = manifest(MANIFEST);
+        let queues = platform.queues().unwrap();
+
+        assert_eq!(
+            differences(&manifest, &queues),
+            [
+                Difference::Missing(&manifest.queues[0]),
+                Difference::Transactional {
+                    name: "elos.audit",
+                    expected: false
+                },
+                Difference::Unexpected(&queues[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn grants_access_to_the_existing_queues() {
+        let platform = MockPlatform::with_queues(&["elos.audit"]);
+
+        grant_access(&platform, "pontus", &manifest(MANIFEST)).unwrap();
+
+        assert_eq!(
+            *platform.calls.borrow(),
+            ["grant_queue_access elos.audit pontus"]
+        );
+    }
+
+    #[test]
+    fn deletes_a_queue_by_any_case() {
+        let platform = MockPlatform::with_queues(&["elos.audit", "elos.notifications"]);
+
+        delete_queue(&platform, "ELOS.AUDIT", true).unwrap();
+
+        assert_eq!(platform.queue_names(), ["elos.notifications"]);
+    }
+
+    #[test]
+    fn purging_an_unknown_queue_fails() {
+        let platform = MockPlatform::with_queues(&["elos.audit"]);
+
+        let error = purge_queue(&platform, "elos.missing", true).unwrap_err();
+
+        assert_eq!(error, "No queue named elos.missing");
+        assert!(platform.calls.borrow().is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+use serde_json::Value;
+
+use crate::{
+    commands::or_exit,
+    db::{self, Connection, SqlClient},
+    output::{self, Format},
+};
+
+#[derive(Clone, Debug)]
+pub(crate) struct Role {
+    pub id: String,
+    pub name: String,
+}
+
+pub(crate) async fn invoke(format: Format, connection: &Connection) {
+    let connection = or_exit(connection.resolve(), "Could not resolve connection string");
+    let mut client = or_exit(
+        db::init_client(&connection).await,
+        "Could not connect to database",
+    );
+
+    let roles = or_exit(fetch_roles(&mut client).await, "Could not list roles");
+    let rows = roles
+        .iter()
+        .map(|role| {
+            vec![
+                Value::from(role.name.as_str()),
+                Value::from(role.id.as_str()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    output::print_rows(&["Name", "Id"], &rows, format);
+}
+
+pub(crate) async fn fetch_roles(
+    client: &mut SqlClient,
+) -> Result<Vec<Role>, tiberius::error::Error> {
+    let query = r#"SELECT CONVERT(nvarchar(36), [Id])
+            ,[Name]
+        FROM [dbo].[UserRole]
+        ORDER BY [Name]"#;
+
+    let rows = client.query(query, &[]).await?.into_first_result().await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some(Role {
+                id: row.get::<&str, _>(0)?.to_string(),
+                name: row.get::<&str, _>(1)?.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Looks up roles by name (case-insensitive) or id, each role once however often it is named.
+/// Fails with the closest role names as suggestions when any of them doesn't match.
+pub(crate) fn match_roles<'a>(
+    roles: &'a [Role],
+    names: &[String],
+) -> Result<Vec<&'a Role>, String> {
+    let mut matched = Vec::new();
+    let mut errors = Vec::new();
+    for name in names {
+        let role = roles.iter().find(|role| {
+            role.name.eq_ignore_ascii_case(name) || role.id.eq_ignore_ascii_case(name)
+        });
+        match role {
+            Some(role) => {
+                if !matched.iter().any(|matched: &&Role| matched.id == role.id) {
+                    matched.push(role);
+                }
+            }
+            None => {
+                let suggestions = suggestions(roles, name);
+                if suggestions.is_empty() {
+                    errors.push(format!("No role named {}", name));
+                } else {
+                    errors.push(format!(
+                        "No role named {}, did you mean {}?",
+                        name,
+                        suggestions.join(" or ")
+                    ));
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(matched)
+    } else {
+        errors.push("Run `tainer roles` to see all roles".to_string());
+        Err(errors.join("\n"))
+    }
+}
+
+fn suggestions(roles: &[Role], name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    let mut scored = roles
+        .iter()
+        .filter_map(|role| {
+            let role_name = role.name.to_lowercase();
+            let distance = edit_distance(&role_name, &name);
+            let close = distance <= 3 || role_name.contains(&name) || name.contains(&role_name);
+            close.then(|| (distance, format!("\"{}\"", role.name)))
+        })
+        .collect::<Vec<_>>();
+    scored.sort();
+    scored.into_iter().take(3).map(|(_, name)| name).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roles() -> Vec<Role> {
+        [
+            ("FE4DA52F-2104-424D-B1C1-B07400E64A44", "ALL FEATURES"),
+            ("2B1E4C0D-5A3F-4E8B-9C7D-1F2A3B4C5D6E", "Planner"),
+            ("7C8D9E0F-1A2B-4C3D-8E4F-5A6B7C8D9E0F", "Read Only"),
+        ]
+        .iter()
+        .map(|(id, name)| Role {
+            id: id.to_string(),
+            name: name.to_string(),
+        })
+        .collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn matched_names(roles: &[Role], names: &[String]) -> Vec<String> {
+        match_roles(roles, names)
+            .unwrap()
+            .iter()
+            .map(|role| role.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn matches_names_in_any_case_and_ids() {
+        let roles = roles();
+
+        assert_eq!(
+            matched_names(
+                &roles,
+                &names(&[
+                    "planner",
+                    "READ ONLY",
+                    "fe4da52f-2104-424d-b1c1-b07400e64a44"
+                ])
+            ),
+            ["Planner", "Read Only", "ALL FEATURES"]
+        );
+    }
+
+    #[test]
+    fn matches_a_role_named_twice_once() {
+        let roles = roles();
+
+        assert_eq!(
+            matched_names(
+                &roles,
+                &names(&["Planner", "planner", "2B1E4C0D-5A3F-4E8B-9C7D-1F2A3B4C5D6E"])
+            ),
+            ["Planner"]
+        );
+    }
+
+    #[test]
+    fn suggests_the_closest_roles() {
+        let roles = roles();
+
+        let error = match_roles(&roles, &names(&["Planer", "Admin"])).unwrap_err();
+
+        assert_eq!(
+            error,
+            "No role named Planer, did you mean \"Planner\"?\n\
+             No role named Admin\n\
+             Run `tainer roles` to see all roles"
+        );
+    }
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("planner", "planner"), 0);
+        assert_eq!(edit_distance("planner", "planer"), 1);
+        assert_eq!(edit_distance("read only", "readonly"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::Read;
+
+use console::style;
+use dialoguer::Password;
+
+use crate::vault::Vault;
+
+/// The value is never an argument, so it stays out of the shell history and the process list.
+pub(crate) fn set(name: &str, stdin: bool) {
+    let mut vault = open(Vault::open());
+    let value = if stdin {
+        let mut value = String::new();
+        std::io::stdin()
+            .read_to_string(&mut value)
+            .expect("Failed to read secret value from stdin");
+        value.trim_end_matches(['\r', '\n']).to_string()
+    } else {
+        Password::new()
+            .with_prompt(format!("Value of {}", name))
+            .interact()
+            .expect("Failed to get secret value")
+    };
+
+    vault.set(name, &value);
+    if let Err(e) = vault.save() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!(
+        "Saved {}, use it as {}",
+        style(name).bold(),
+        style(format!("${{secret:{}}}", name)).bold().dim()
+    );
+}
+
+pub(crate) fn get(name: &str) {
+    let vault = open(Vault::open_existing());
+    match vault.get(name) {
+        Some(value) => println!("{}", value),
+        None => {
+            eprintln!("No secret named {}", name);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub(crate) fn list() {
+    let vault = open(Vault::open_existing());
+    for name in vault.names() {
+        println!("{}", name);
+    }
+}
+
+pub(crate) fn remove(name: &str) {
+    let mut vault = open(Vault::open_existing());
+    if !vault.remove(name) {
+        eprintln!("No secret named {}", name);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = vault.save() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!("Removed {}", style(name).bold());
+}
+
+fn open(vault: Result<Vault, String>) -> Vault {
+    vault.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
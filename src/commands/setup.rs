@@ -1,56 +1,59 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use console::style;
 use dialoguer::Input;
 
 use crate::{
-    commands, git,
-    win::{self},
+    commands::{
+        self,
+        create_user::{self, CreateUserError, NewUser, UserOptions},
+        queues::{self, Manifest},
+    },
+    config::{self, UserDefaults},
+    db::{self, Connection, ConnectionArgs, SqlClient},
+    git,
+    platform::Platform,
+    store::UserStore,
 };
 
+/// Directories with schema scripts to run against the databases setup creates.
+pub(crate) struct Bootstrap {
+    pub main: Option<PathBuf>,
+    pub service_bus: Option<PathBuf>,
+}
+
 pub(crate) async fn invoke(
-    main_connection_string: &Option<String>,
+    db: &ConnectionArgs,
     service_bus_connection_string: &Option<String>,
+    protect: bool,
+    bootstrap: &Bootstrap,
     root_path: &Path,
+    platform: &dyn Platform,
 ) {
     println!("Running setup command");
     git::init_submodules(root_path);
 
-    // check if MSMQ is installed
-    println!("Checking if MSMQ is enabled...");
-    let msmq_enabled = win::msmq_enabled();
-    if !msmq_enabled {
-        println!("MSMQ is not enabled, enabling it now.");
-        match win::enable_msmq() {
-            Ok(_) => println!("MSMQ has been enabled successfully."),
-            Err(e) => {
-                eprintln!("Failed to enable MSMQ: {}", e);
-                return;
-            }
+    if platform.has_msmq() {
+        if let Err(e) = set_up_queues(platform, root_path) {
+            eprintln!("{}", e);
+            return;
         }
+    } else {
+        println!("⚠️  - MSMQ is only available on Windows, skipping the queues");
     }
 
-    let account_name = win::get_account_name();
-    println!("Account name: {}", account_name);
-    match win::execute_ps1(
-        root_path
-            .join("ELOSQueues.ps1")
-            .to_str()
-            .expect("ELOSQueues.ps1 path is not valid"),
-        &["-account", &account_name],
-    ) {
-        Ok(_) => println!("ELOSQueues.ps1 has been executed successfully."),
-        Err(e) => eprintln!("Failed to execute ELOSQueues.ps1: {}", e),
-    }
-
-    // Set up connection strings
-    let main_connection_string = if let Some(main) = main_connection_string {
-        main.clone()
-    } else {
-        Input::new()
-            .with_prompt("Main connection string (dbEnvirotainerELOS)")
-            .interact()
-            .expect("Failed to get main connection string")
-    };
+    // Set up connection strings, the login and TLS options are the same for both databases
+    let main = db
+        .connection_or_else(|| {
+            Input::new()
+                .with_prompt("Main connection string (dbEnvirotainerELOS)")
+                .interact()
+                .expect("Failed to get main connection string")
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
     let service_bus_connection_string = if let Some(service_bus) = service_bus_connection_string {
         service_bus.clone()
     } else {
@@ -59,10 +62,33 @@ pub(crate) async fn invoke(
             .interact()
             .expect("Failed to get service bus connection string")
     };
+    // Create the databases before anything tries to use them
+    let service_bus = main.with_connection_string(&service_bus_connection_string);
+    let mut databases = Vec::new();
+    for (connection, bootstrap_dir) in [
+        (&main, &bootstrap.main),
+        (&service_bus, &bootstrap.service_bus),
+    ] {
+        let (server, database) = connect_to_master(connection).await.unwrap_or_else(|e| {
+            eprintln!("Failed to create database: {}", e);
+            std::process::exit(1);
+        });
+        databases.push((
+            server,
+            database,
+            bootstrap_dir.as_ref().map(|dir| root_path.join(dir)),
+        ));
+    }
+    if let Err(e) = create_databases(&mut databases).await {
+        eprintln!("Failed to create database: {}", e);
+        std::process::exit(1);
+    }
     commands::connection_strings::invoke(
-        &main_connection_string,
+        &main.connection_string,
         &service_bus_connection_string,
+        protect,
         root_path,
+        platform,
     );
     // Fix applicationhost
     commands::application_host::invoke(root_path);
@@ -77,7 +103,470 @@ pub(crate) async fn invoke(
         .with_prompt("Email of the new user (e.g. pontus.backman@spinit.se)")
         .interact()
         .expect("Failed to get user email");
-    commands::create_user::invoke(&user_name, &user_email, &main_connection_string).await;
+    if let Err(e) = add_first_user(&user_name, &user_email, &main, db.profile_name()).await {
+        eprintln!("Failed to create user: {}", e);
+    }
 
     println!("Setup command has finished.");
 }
+
+/// Enables MSMQ if it isn't, and creates the queues for the current account from the queue
+/// manifest, or with `ELOSQueues.ps1` if the superproject has none. Only fails if MSMQ can't
+/// be enabled.
+fn set_up_queues(platform: &dyn Platform, root_path: &Path) -> Result<(), String> {
+    println!("Checking if MSMQ is enabled...");
+    if !platform.msmq_enabled()? {
+        println!("MSMQ is not enabled, enabling it now.");
+        platform
+            .enable_msmq()
+            .map_err(|e| format!("Failed to enable MSMQ: {}", e))?;
+        println!("MSMQ has been enabled successfully.");
+    }
+
+    let account_name = platform.account_name();
+    println!("Account name: {}", account_name);
+    let manifest_path = root_path.join(queues::DEFAULT_MANIFEST);
+    if manifest_path.exists() {
+        if let Err(e) = Manifest::load(&manifest_path).and_then(|manifest| {
+            queues::create_queues(platform, &manifest)?;
+            queues::grant_access(platform, &account_name, &manifest)
+        }) {
+            eprintln!("Failed to set up the queues: {}", e);
+        }
+        return Ok(());
+    }
+
+    match platform.execute_ps1(
+        &root_path.join("ELOSQueues.ps1"),
+        &["-account", &account_name],
+    ) {
+        Ok(_) => println!("ELOSQueues.ps1 has been executed successfully."),
+        Err(e) => eprintln!("Failed to execute ELOSQueues.ps1: {}", e),
+    }
+    Ok(())
+}
+
+async fn add_first_user(
+    name: &str,
+    email: &str,
+    connection: &Connection,
+    profile: Option<&str>,
+) -> Result<(), CreateUserError> {
+    let config = config::load().map_err(CreateUserError::Config)?;
+    let mut store = create_user::connect(connection).await?;
+    create_first_user(&mut store, name, email, &config.user_defaults(profile)).await
+}
+
+/// The user setup asks for gets `ALL FEATURES` and the configured defaults for everything
+/// else, so the new environment can be logged in to right away.
+async fn create_first_user(
+    store: &mut impl UserStore,
+    name: &str,
+    email: &str,
+    defaults: &UserDefaults,
+) -> Result<(), CreateUserError> {
+    let user = NewUser::new(name, email, &UserOptions::default(), defaults)
+        .map_err(CreateUserError::InvalidUser)?;
+    create_user::create(store, &user, &[]).await
+}
+
+/// What setup needs from the database server to create a database, so it can run against
+/// something other than SQL Server.
+trait DatabaseServer {
+    async fn database_exists(&mut self, database: &str) -> Result<bool, String>;
+
+    async fn create_database(&mut self, database: &str) -> Result<(), String>;
+
+    async fn drop_database(&mut self, database: &str) -> Result<(), String>;
+
+    /// Runs the scripts in `dir` against the database in the connection string.
+    async fn bootstrap(&mut self, dir: &Path) -> Result<(), String>;
+}
+
+/// A SQL Server, through a client connected to `master`.
+struct SqlDatabaseServer {
+    master: SqlClient,
+    connection: Connection,
+}
+
+impl SqlDatabaseServer {
+    async fn run(&mut self, sql: String) -> Result<(), tiberius::error::Error> {
+        self.master.simple_query(sql).await?.into_results().await?;
+        Ok(())
+    }
+}
+
+impl DatabaseServer for SqlDatabaseServer {
+    async fn database_exists(&mut self, database: &str) -> Result<bool, String> {
+        let exists = self
+            .master
+            .query(
+                "SELECT CASE WHEN DB_ID(@P1) IS NULL THEN 0 ELSE 1 END",
+                &[&database],
+            )
+            .await
+            .map_err(|e| format!("Could not look up {}: {}", database, e))?
+            .into_row()
+            .await
+            .map_err(|e| format!("Could not look up {}: {}", database, e))?
+            .and_then(|row| row.get::<i32, _>(0))
+            == Some(1);
+        Ok(exists)
+    }
+
+    async fn create_database(&mut self, database: &str) -> Result<(), String> {
+        self.run(format!("CREATE DATABASE {}", db::quote_name(database)))
+            .await
+            .map_err(|e| format!("Could not create {}: {}", database, e))
+    }
+
+    async fn drop_database(&mut self, database: &str) -> Result<(), String> {
+        // a failed script may leave a session behind
+        let database = db::quote_name(database);
+        self.run(format!(
+            "ALTER DATABASE {0} SET SINGLE_USER WITH ROLLBACK IMMEDIATE; DROP DATABASE {0}",
+            database
+        ))
+        .await
+        .map_err(|e| format!("Could not drop {}: {}", database, e))
+    }
+
+    async fn bootstrap(&mut self, dir: &Path) -> Result<(), String> {
+        let mut client = db::init_client(&self.connection)
+            .await
+            .map_err(|e| format!("Could not connect to the new database: {}", e))?;
+        commands::db_seed::run_scripts(&mut client, dir, false, true).await
+    }
+}
+
+/// Connects to `master` on the server of the connection string, returning the server and the
+/// name of the database in the connection string.
+async fn connect_to_master(connection: &Connection) -> Result<(SqlDatabaseServer, String), String> {
+    let connection = connection
+        .resolve()
+        .map_err(|e| format!("Could not resolve connection string: {}", e))?;
+    let database = db::database_name(&connection.connection_string)
+        .ok_or("The connection string has no Initial Catalog or Database")?;
+
+    let master = db::init_master_client(&connection)
+        .await
+        .map_err(|e| format!("Could not connect to master: {}", e))?;
+    Ok((SqlDatabaseServer { master, connection }, database))
+}
+
+/// Creates the databases in order, stopping at the first that fails since everything after
+/// it in setup needs them.
+async fn create_databases(
+    databases: &mut [(impl DatabaseServer, String, Option<PathBuf>)],
+) -> Result<(), String> {
+    for (server, database, bootstrap_dir) in databases {
+        create_database(server, database, bootstrap_dir.as_deref()).await?;
+    }
+    Ok(())
+}
+
+/// Creates and bootstraps `database` if it doesn't exist. A database whose bootstrap fails is
+/// dropped again, since the next setup would leave it alone half-done.
+async fn create_database(
+    server: &mut impl DatabaseServer,
+    database: &str,
+    bootstrap_dir: Option<&Path>,
+) -> Result<(), String> {
+    println!("Checking if database {} exists...", database);
+    if server.database_exists(database).await? {
+        println!("Database {} already exists.", database);
+        return Ok(());
+    }
+
+    println!("Database {} does not exist, creating it now.", database);
+    server.create_database(database).await?;
+    println!("✅ - created database {}", style(database).bold());
+
+    let Some(dir) = bootstrap_dir else {
+        return Ok(());
+    };
+    println!("Running bootstrap scripts from {}", dir.display());
+    if let Err(e) = server.bootstrap(dir).await {
+        return match server.drop_database(database).await {
+            Ok(()) => Err(format!(
+                "{}\nDropped {} again, fix the scripts and run setup again",
+                e, database
+            )),
+            Err(drop_error) => Err(format!(
+                "{}\n{}, drop it before running setup again",
+                e, drop_error
+            )),
+        };
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::create_user::ALL_FEATURES_ROLE_ID, platform::MockPlatform,
+        store::InMemoryUserStore,
+    };
+
+    #[tokio::test]
+    async fn first_user_gets_all_features_and_the_defaults() {
+        let mut store = InMemoryUserStore::default();
+        let defaults = UserDefaults {
+            locale: "en-GB".to_string(),
+            office_id: Some("office".to_string()),
+            ..Default::default()
+        };
+
+        create_first_user(&mut store, "Pontus Backman", "pontus@spinit.se", &defaults)
+            .await
+            .unwrap();
+
+        let (_, user) = &store.users[0];
+        assert_eq!(user.username, "pontus@spinit.se");
+        assert_eq!(user.locale, "en-GB");
+        assert_eq!(user.office_id.as_deref(), Some("office"));
+        assert_eq!(store.roles_of("pontus@spinit.se"), [ALL_FEATURES_ROLE_ID]);
+    }
+
+    #[tokio::test]
+    async fn rerunning_setup_keeps_one_user() {
+        let mut store = InMemoryUserStore::default();
+        let defaults = UserDefaults::default();
+        create_first_user(&mut store, "Pontus Backman", "pontus@spinit.se", &defaults)
+            .await
+            .unwrap();
+
+        let result =
+            create_first_user(&mut store, "Pontus Backman", "pontus@spinit.se", &defaults).await;
+
+        assert!(matches!(result, Err(CreateUserError::UserExists(_))));
+        assert_eq!(store.users.len(), 1);
+        assert_eq!(store.user_roles.len(), 1);
+    }
+
+    /// Records what setup asks of the server, with the databases that exist.
+    #[derive(Default)]
+    struct FakeDatabaseServer {
+        databases: Vec<String>,
+        bootstrap_error: Option<String>,
+        calls: Vec<String>,
+    }
+
+    impl DatabaseServer for FakeDatabaseServer {
+        async fn database_exists(&mut self, database: &str) -> Result<bool, String> {
+            self.calls.push(format!("exists {}", database));
+            Ok(self.databases.iter().any(|name| name == database))
+        }
+
+        async fn create_database(&mut self, database: &str) -> Result<(), String> {
+            self.calls.push(format!("create {}", database));
+            self.databases.push(database.to_string());
+            Ok(())
+        }
+
+        async fn drop_database(&mut self, database: &str) -> Result<(), String> {
+            self.calls.push(format!("drop {}", database));
+            self.databases.retain(|name| name != database);
+            Ok(())
+        }
+
+        async fn bootstrap(&mut self, dir: &Path) -> Result<(), String> {
+            self.calls.push(format!("bootstrap {}", dir.display()));
+            self.bootstrap_error.clone().map_or(Ok(()), Err)
+        }
+    }
+
+    #[tokio::test]
+    async fn creates_and_bootstraps_a_missing_database() {
+        let mut server = FakeDatabaseServer::default();
+
+        create_database(&mut server, "Elos", Some(Path::new("bootstrap")))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            server.calls,
+            ["exists Elos", "create Elos", "bootstrap bootstrap"]
+        );
+        assert_eq!(server.databases, ["Elos"]);
+    }
+
+    #[tokio::test]
+    async fn leaves_an_existing_database_alone() {
+        let mut server = FakeDatabaseServer {
+            databases: vec!["Elos".to_string()],
+            ..Default::default()
+        };
+
+        create_database(&mut server, "Elos", Some(Path::new("bootstrap")))
+            .await
+            .unwrap();
+
+        assert_eq!(server.calls, ["exists Elos"]);
+    }
+
+    #[tokio::test]
+    async fn drops_the_database_when_the_bootstrap_fails() {
+        let mut server = FakeDatabaseServer {
+            bootstrap_error: Some("001_schema.sql failed".to_string()),
+            ..Default::default()
+        };
+
+        let error = create_database(&mut server, "Elos", Some(Path::new("bootstrap")))
+            .await
+            .unwrap_err();
+
+        assert!(error.starts_with("001_schema.sql failed"), "{}", error);
+        assert_eq!(
+            server.calls,
+            [
+                "exists Elos",
+                "create Elos",
+                "bootstrap bootstrap",
+                "drop Elos"
+            ]
+        );
+        assert!(server.databases.is_empty());
+
+        // so the next setup creates and bootstraps it again
+        server.bootstrap_error = None;
+        create_database(&mut server, "Elos", Some(Path::new("bootstrap")))
+            .await
+            .unwrap();
+        assert_eq!(server.databases, ["Elos"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_answers_never_reach_the_database() {
+        let mut store = InMemoryUserStore::default();
+
+        let result = create_first_user(
+            &mut store,
+            "Pontus Backman",
+            "pontus.backman",
+            &UserDefaults::default(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(CreateUserError::InvalidUser(_))));
+        assert_eq!(store.commits + store.rollbacks, 0);
+    }
+
+    #[test]
+    fn enables_msmq_before_creating_the_queues() {
+        let platform = MockPlatform {
+            msmq: Some(false.into()),
+            ..Default::default()
+        };
+
+        set_up_queues(&platform, Path::new("elos")).unwrap();
+
+        assert_eq!(
+            *platform.calls.borrow(),
+            [
+                "msmq_enabled",
+                "enable_msmq",
+                "ELOSQueues.ps1 -account pontus"
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_enabled_msmq_alone() {
+        let platform = MockPlatform::default();
+
+        set_up_queues(&platform, Path::new("elos")).unwrap();
+
+        assert_eq!(
+            *platform.calls.borrow(),
+            ["msmq_enabled", "ELOSQueues.ps1 -account pontus"]
+        );
+    }
+
+    #[test]
+    fn stops_when_msmq_cannot_be_enabled() {
+        let platform = MockPlatform {
+            msmq: Some(false.into()),
+            enable_error: Some("Please run this command in an admin shell.".to_string()),
+            ..Default::default()
+        };
+
+        let error = set_up_queues(&platform, Path::new("elos")).unwrap_err();
+
+        assert!(error.contains("admin shell"), "{}", error);
+        assert_eq!(*platform.calls.borrow(), ["msmq_enabled", "enable_msmq"]);
+    }
+
+    #[test]
+    fn creates_the_queues_from_the_manifest() {
+        let root = std::env::temp_dir().join(format!("tainer-setup-queues-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join(queues::DEFAULT_MANIFEST),
+            "[[queue]]\nname = \"elos.audit\"\n",
+        )
+        .unwrap();
+        let platform = MockPlatform::default();
+
+        let result = set_up_queues(&platform, &root);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            *platform.calls.borrow(),
+            [
+                "msmq_enabled",
+                "create_queue elos.audit",
+                "grant_queue_access elos.audit pontus"
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_queue_script_does_not_stop_setup() {
+        let platform = MockPlatform {
+            script_error: Some("Failed to execute PowerShell script.".to_string()),
+            ..Default::default()
+        };
+
+        assert!(set_up_queues(&platform, Path::new("elos")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_first_database_that_fails() {
+        let failing = FakeDatabaseServer {
+            bootstrap_error: Some("Invalid object name 'Tenant'".to_string()),
+            ..Default::default()
+        };
+        let mut databases = [
+            (
+                failing,
+                "Elos".to_string(),
+                Some(PathBuf::from("bootstrap")),
+            ),
+            (
+                FakeDatabaseServer::default(),
+                "NServiceBus".to_string(),
+                None,
+            ),
+        ];
+
+        let result = create_databases(&mut databases).await;
+
+        assert!(result
+            .unwrap_err()
+            .starts_with("Invalid object name 'Tenant'"));
+        assert_eq!(
+            databases[0].0.calls,
+            [
+                "exists Elos",
+                "create Elos",
+                "bootstrap bootstrap",
+                "drop Elos"
+            ]
+        );
+        assert!(databases[1].0.calls.is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::Path;
+
+use console::style;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::git::{self, find_git_modules, GitModule};
+
+/// Prefixed to the label in the stash message, so only stashes made by tainer are found.
+static MESSAGE_PREFIX: &str = "tainer:";
+
+fn stash_message(label: &str) -> String {
+    format!("{}{}", MESSAGE_PREFIX, label)
+}
+
+pub(crate) fn push(label: &str, include_untracked: bool, root_path: &Path) {
+    let git_modules = find_git_modules(root_path);
+    let dirty_modules = git_modules
+        .par_iter()
+        .filter(|module| git::is_dirty(module, include_untracked))
+        .collect::<Vec<_>>();
+
+    if dirty_modules.is_empty() {
+        println!("No dirty repositories found, nothing to stash.");
+        return;
+    }
+
+    if dirty_modules
+        .iter()
+        .any(|module| find_stash(module, label).is_some())
+    {
+        eprintln!(
+            "A stash labeled {} already exists, pop it or pick another label.",
+            style(label).bold()
+        );
+        std::process::exit(1);
+    }
+
+    let message = stash_message(label);
+    let failed = dirty_modules
+        .par_iter()
+        .filter(|module| {
+            let mut args = vec!["stash", "push", "-m", &message];
+            if include_untracked {
+                args.push("--include-untracked");
+            }
+            let status = std::process::Command::new("git")
+                .args(&args)
+                .current_dir(&module.path)
+                .output()
+                .expect("Failed to run git command")
+                .status;
+
+            if status.success() {
+                println!(
+                    "✅ - stashed {} as {}",
+                    style(&module.name).bold(),
+                    style(label).bold().dim()
+                );
+            } else {
+                println!("❌ - failed to stash {}", style(&module.name).bold().red());
+            }
+            !status.success()
+        })
+        .count();
+    if failed > 0 {
+        eprintln!("Failed to stash {} repositories", failed);
+        std::process::exit(1);
+    }
+}
+
+pub(crate) fn pop(label: &str, root_path: &Path) {
+    let git_modules = find_git_modules(root_path);
+    let stashed_modules = git_modules
+        .par_iter()
+        .filter_map(|module| find_stash(module, label).map(|stash_ref| (module, stash_ref)))
+        .collect::<Vec<_>>();
+
+    if stashed_modules.is_empty() {
+        eprintln!("No stashes labeled {} found.", style(label).bold());
+        return;
+    }
+
+    // pop one repository at a time so we can stop at the first conflict
+    for (i, (module, stash_ref)) in stashed_modules.iter().enumerate() {
+        let output = std::process::Command::new("git")
+            .args(["stash", "pop", stash_ref])
+            .current_dir(&module.path)
+            .output()
+            .expect("Failed to run git command");
+
+        if output.status.success() {
+            println!(
+                "✅ - restored {} in {}",
+                style(label).bold().dim(),
+                style(&module.name).bold()
+            );
+            continue;
+        }
+
+        println!(
+            "❌ - failed to restore {} in {}",
+            style(label).bold().dim(),
+            style(&module.name).bold().red()
+        );
+        let conflicts = git::conflicted_files(module);
+        if conflicts.is_empty() {
+            eprintln!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        } else {
+            eprintln!("Conflicts:");
+            for file in &conflicts {
+                eprintln!("  {}", style(file).red());
+            }
+        }
+
+        let remaining = &stashed_modules[i + 1..];
+        if !remaining.is_empty() {
+            eprintln!(
+                "Stopped before restoring {} remaining repositories:",
+                remaining.len()
+            );
+            for (module, _) in remaining {
+                eprintln!("  {}", style(&module.name).bold());
+            }
+        }
+        if conflicts.is_empty() {
+            eprintln!(
+                "Fix the problem in {} and run {} again.",
+                style(&module.name).bold(),
+                style(format!("tainer stash pop {}", label)).bold().dim()
+            );
+        } else {
+            // git keeps the stash after a conflict, popping it again would apply it twice
+            eprintln!(
+                "Resolve the conflicts in {}, then drop the applied stash there with {}.",
+                style(&module.name).bold(),
+                style(format!("git stash drop {}", stash_ref)).bold().dim()
+            );
+            if !remaining.is_empty() {
+                eprintln!(
+                    "Run {} again afterwards to restore the remaining repositories.",
+                    style(format!("tainer stash pop {}", label)).bold().dim()
+                );
+            }
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Returns the stash reference (e.g. `stash@{1}`) of the newest stash with the given label.
+fn find_stash(module: &GitModule, label: &str) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .args(["stash", "list", "--format=%gd%x00%gs"])
+        .current_dir(&module.path)
+        .output()
+        .ok()?;
+
+    find_in_stash_list(&String::from_utf8_lossy(&output.stdout), label)
+}
+
+fn find_in_stash_list(stash_list: &str, label: &str) -> Option<String> {
+    // stash subjects look like "On <branch>: <message>", and branch names can't contain ':'
+    let message = stash_message(label);
+    stash_list
+        .lines()
+        .filter_map(|line| line.split_once('\0'))
+        .find(|(_, subject)| {
+            subject
+                .split_once(": ")
+                .is_some_and(|(_, stashed)| stashed == message)
+        })
+        .map(|(stash_ref, _)| stash_ref.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_only_the_exact_label() {
+        let stash_list = [
+            "stash@{0}\0On main: tainer:b: a",
+            "stash@{1}\0On main: a",
+            "stash@{2}\0On feature/vqt: tainer:a",
+            "stash@{3}\0On main: tainer:a",
+        ]
+        .join("\n");
+
+        assert_eq!(
+            find_in_stash_list(&stash_list, "a").as_deref(),
+            Some("stash@{2}")
+        );
+        assert_eq!(
+            find_in_stash_list(&stash_list, "b: a").as_deref(),
+            Some("stash@{0}")
+        );
+        assert_eq!(find_in_stash_list(&stash_list, "b"), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+
+use console::style;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::git;
+
+/// Clears skip-worktree on the config files `--protect` marked. Other skip-worktree files,
+/// marked by hand or by a sparse checkout, are left alone.
+pub(crate) fn invoke(root_path: &Path) {
+    let git_modules = git::find_git_modules(root_path);
+    git_modules.par_iter().for_each(|module| {
+        let protected_configs = git::protected_files(module);
+        if protected_configs.is_empty() {
+            return;
+        }
+        if git::is_sparse_checkout(module) {
+            println!(
+                "⚠️  - {} uses a sparse checkout, unprotect its files with `git update-index --no-skip-worktree`",
+                style(&module.name).bold().yellow()
+            );
+            return;
+        }
+
+        let mut still_protected = Vec::new();
+        for path in protected_configs {
+            let short_path = path.strip_prefix(root_path).unwrap_or(&path).display();
+            if git::set_skip_worktree(&path, false) {
+                println!("✅ - unprotected {}", style(short_path).bold());
+            } else {
+                println!(
+                    "❌ - failed to unprotect {}",
+                    style(short_path).bold().red()
+                );
+                still_protected.push(path);
+            }
+        }
+        git::set_protected_files(module, &still_protected);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("Failed to run git command")
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn leaves_files_it_did_not_protect_alone() {
+        let root = std::env::temp_dir().join(format!("tainer-unprotect-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("Api")).unwrap();
+        std::fs::write(root.join("Api").join("web.config"), "<configuration />").unwrap();
+        std::fs::write(root.join("app.config"), "<configuration />").unwrap();
+        git(&root, &["init", "--quiet"]);
+        git(&root, &["add", "."]);
+        git(
+            &root,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "--quiet",
+                "-m",
+                "init",
+            ],
+        );
+        let protected = root.join("Api").join("web.config");
+        assert!(git::set_skip_worktree(&protected, true) && git::record_protected(&protected));
+        git(&root, &["update-index", "--skip-worktree", "app.config"]);
+
+        invoke(&root);
+        let module = git::GitModule {
+            path: root.clone(),
+            name: "root".to_string(),
+        };
+        let skipped = git::skip_worktree_files(&module);
+        let recorded = git::protected_files(&module);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(skipped, [root.join("app.config")]);
+        assert!(recorded.is_empty());
+    }
+}
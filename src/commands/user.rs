@@ -0,0 +1,387 @@
+use console::style;
+use dialoguer::Confirm;
+use serde_json::Value;
+use tiberius::Row;
+
+use crate::{
+    commands::{or_exit, roles},
+    db::{self, Connection, SqlClient},
+    output::{self, Format},
+};
+
+static LIST_COLUMNS: [&str; 6] = ["Id", "FirstName", "LastName", "Email", "Active", "Roles"];
+static SHOW_COLUMNS: [&str; 13] = [
+    "Id",
+    "FirstName",
+    "LastName",
+    "Email",
+    "Username",
+    "Timezone",
+    "Locale",
+    "Active",
+    "SystemUser",
+    "OfficeId",
+    "DepartmentId",
+    "LastLogin",
+    "Roles",
+];
+
+pub(crate) async fn list(email_like: &Option<String>, format: Format, connection: &Connection) {
+    let mut client = connect(connection).await;
+    let pattern = email_like.as_deref().map(like_pattern);
+
+    or_exit(
+        db::begin_transaction(&mut client).await,
+        "Could not begin transaction",
+    );
+    let result = list_users(&mut client, pattern.as_deref()).await;
+    or_exit(
+        db::end_transaction(&mut client, result.is_ok()).await,
+        "Could not end transaction",
+    );
+
+    let rows = or_exit(result, "Could not list users");
+    output::print_rows(&LIST_COLUMNS, &rows, format);
+}
+
+pub(crate) async fn show(email: &str, format: Format, connection: &Connection) {
+    let mut client = connect(connection).await;
+
+    or_exit(
+        db::begin_transaction(&mut client).await,
+        "Could not begin transaction",
+    );
+    let result = find_user(&mut client, email).await;
+    or_exit(
+        db::end_transaction(&mut client, result.is_ok()).await,
+        "Could not end transaction",
+    );
+
+    match or_exit(result, "Could not get user") {
+        Some(user) => output::print_record(&SHOW_COLUMNS, &user, format),
+        None => {
+            eprintln!("No user with email {}", email);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub(crate) async fn set_active(email: &str, active: bool, connection: &Connection) {
+    let mut client = connect(connection).await;
+
+    or_exit(
+        db::begin_transaction(&mut client).await,
+        "Could not begin transaction",
+    );
+    let result = update_active(&mut client, email, active).await;
+    or_exit(
+        db::end_transaction(&mut client, result.is_ok()).await,
+        "Could not end transaction",
+    );
+
+    let state = if active { "Activated" } else { "Deactivated" };
+    match or_exit(result, "Could not update user") {
+        0 => {
+            eprintln!("No user with email {}", email);
+            std::process::exit(1);
+        }
+        _ => println!("{} user {}", state, style(email).bold()),
+    }
+}
+
+pub(crate) async fn delete(email: &str, yes: bool, connection: &Connection) {
+    if !yes
+        && !Confirm::new()
+            .with_prompt(format!("Delete user {} and their roles?", email))
+            .interact()
+            .expect("Failed to get confirmation")
+    {
+        return;
+    }
+
+    let mut client = connect(connection).await;
+
+    or_exit(
+        db::begin_transaction(&mut client).await,
+        "Could not begin transaction",
+    );
+    let result = delete_user(&mut client, email).await;
+    or_exit(
+        db::end_transaction(&mut client, result.is_ok()).await,
+        "Could not end transaction",
+    );
+
+    match or_exit(result, "Could not delete user") {
+        0 => {
+            eprintln!("No user with email {}", email);
+            std::process::exit(1);
+        }
+        _ => println!("Deleted user {}", style(email).bold()),
+    }
+}
+
+/// Replaces all roles of the user with the given role, by name or id.
+pub(crate) async fn set_role(email: &str, role: &str, connection: &Connection) {
+    let mut client = connect(connection).await;
+
+    or_exit(
+        db::begin_transaction(&mut client).await,
+        "Could not begin transaction",
+    );
+    let result = replace_roles(&mut client, email, role).await;
+    // nothing is changed unless both the user and the role exist
+    let changed = matches!(result, Ok(Ok(_)));
+    or_exit(
+        db::end_transaction(&mut client, changed).await,
+        "Could not end transaction",
+    );
+
+    match or_exit(result, "Could not set role") {
+        Ok(_) => println!(
+            "Set role of {} to {}",
+            style(email).bold(),
+            style(role).bold()
+        ),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn connect(connection: &Connection) -> SqlClient {
+    let connection = or_exit(connection.resolve(), "Could not resolve connection string");
+    or_exit(
+        db::init_client(&connection).await,
+        "Could not connect to database",
+    )
+}
+
+/// The search term matches anywhere in the email. Only `*` is a wildcard, so the `_` in an
+/// email like `first_last@spinit.se` matches itself and not any character.
+fn like_pattern(term: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in term.chars() {
+        match c {
+            '*' => pattern.push('%'),
+            '\\' | '%' | '_' | '[' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            _ => pattern.push(c),
+        }
+    }
+    pattern.push('%');
+    pattern
+}
+
+async fn list_users(
+    client: &mut SqlClient,
+    pattern: Option<&str>,
+) -> Result<Vec<Vec<Value>>, tiberius::error::Error> {
+    let query = r#"SELECT CONVERT(nvarchar(36), [Id])
+            ,[FirstName]
+            ,[LastName]
+            ,[Email]
+            ,[Active]
+        FROM [dbo].[User]
+        WHERE @P1 IS NULL OR [Email] LIKE @P1 ESCAPE '\'
+        ORDER BY [Email]"#;
+
+    let users = client
+        .query(query, &[&pattern])
+        .await?
+        .into_first_result()
+        .await?;
+    let roles = user_roles(client, None).await?;
+
+    Ok(users
+        .iter()
+        .map(|row| {
+            let id = text(row, 0);
+            vec![
+                id.clone(),
+                text(row, 1),
+                text(row, 2),
+                text(row, 3),
+                flag(row, 4),
+                roles_of(&roles, &id),
+            ]
+        })
+        .collect())
+}
+
+async fn find_user(
+    client: &mut SqlClient,
+    email: &str,
+) -> Result<Option<Vec<Value>>, tiberius::error::Error> {
+    let query = r#"SELECT CONVERT(nvarchar(36), [Id])
+            ,[FirstName]
+            ,[LastName]
+            ,[Email]
+            ,[Username]
+            ,[Timezone]
+            ,[Locale]
+            ,[Active]
+            ,[SystemUser]
+            ,CONVERT(nvarchar(36), [OfficeId])
+            ,CONVERT(nvarchar(36), [DepartmentId])
+            ,CONVERT(nvarchar(19), [LastLogin], 120)
+        FROM [dbo].[User]
+        WHERE [Email] = @P1"#;
+
+    let Some(row) = client.query(query, &[&email]).await?.into_row().await? else {
+        return Ok(None);
+    };
+    let id = text(&row, 0);
+    let roles = user_roles(client, id.as_str()).await?;
+
+    Ok(Some(vec![
+        id.clone(),
+        text(&row, 1),
+        text(&row, 2),
+        text(&row, 3),
+        text(&row, 4),
+        text(&row, 5),
+        text(&row, 6),
+        flag(&row, 7),
+        flag(&row, 8),
+        text(&row, 9),
+        text(&row, 10),
+        text(&row, 11),
+        roles_of(&roles, &id),
+    ]))
+}
+
+/// Returns `(user id, role name)` pairs, for one user or for everyone.
+async fn user_roles(
+    client: &mut SqlClient,
+    user_id: Option<&str>,
+) -> Result<Vec<(String, String)>, tiberius::error::Error> {
+    let query = r#"SELECT CONVERT(nvarchar(36), utr.[UserId])
+            ,r.[Name]
+        FROM [dbo].[UsersToRoles] utr
+        JOIN [dbo].[UserRole] r ON r.[Id] = utr.[UserRoleId]
+        WHERE @P1 IS NULL OR utr.[UserId] = @P1
+        ORDER BY r.[Name]"#;
+
+    let rows = client
+        .query(query, &[&user_id])
+        .await?
+        .into_first_result()
+        .await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some((
+                row.get::<&str, _>(0)?.to_string(),
+                row.get::<&str, _>(1)?.to_string(),
+            ))
+        })
+        .collect())
+}
+
+async fn update_active(
+    client: &mut SqlClient,
+    email: &str,
+    active: bool,
+) -> Result<u64, tiberius::error::Error> {
+    let query = r#"UPDATE [dbo].[User]
+        SET [Active] = @P2
+            ,[ActivationChanged] = GETDATE()
+        WHERE [Email] = @P1"#;
+
+    Ok(client.execute(query, &[&email, &active]).await?.total())
+}
+
+async fn delete_user(client: &mut SqlClient, email: &str) -> Result<u64, tiberius::error::Error> {
+    let query = r#"DELETE utr
+        FROM [dbo].[UsersToRoles] utr
+        JOIN [dbo].[User] u ON u.[Id] = utr.[UserId]
+        WHERE u.[Email] = @P1"#;
+    client.execute(query, &[&email]).await?;
+
+    let query = r#"DELETE FROM [dbo].[User]
+        WHERE [Email] = @P1"#;
+    Ok(client.execute(query, &[&email]).await?.total())
+}
+
+/// The outer result is for database errors, the inner one for a missing user or role.
+async fn replace_roles(
+    client: &mut SqlClient,
+    email: &str,
+    role: &str,
+) -> Result<Result<(), String>, tiberius::error::Error> {
+    let query = r#"SELECT CONVERT(nvarchar(36), [Id])
+        FROM [dbo].[User]
+        WHERE [Email] = @P1"#;
+    let Some(user_id) = single_text(client, query, email).await? else {
+        return Ok(Err(format!("No user with email {}", email)));
+    };
+
+    let roles = roles::fetch_roles(client).await?;
+    let role_id = match roles::match_roles(&roles, &[role.to_string()]) {
+        Ok(matched) => matched[0].id.clone(),
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let query = r#"DELETE FROM [dbo].[UsersToRoles]
+        WHERE [UserId] = @P1"#;
+    client.execute(query, &[&user_id]).await?;
+
+    let query = r#"INSERT INTO [dbo].[UsersToRoles]
+            ([UserId]
+            ,[UserRoleId])
+        VALUES
+            (@P1
+            ,@P2)"#;
+    client.execute(query, &[&user_id, &role_id]).await?;
+
+    Ok(Ok(()))
+}
+
+async fn single_text(
+    client: &mut SqlClient,
+    query: &str,
+    param: &str,
+) -> Result<Option<String>, tiberius::error::Error> {
+    let row = client.query(query, &[&param]).await?.into_row().await?;
+    Ok(row.and_then(|row| row.get::<&str, _>(0).map(|s| s.to_string())))
+}
+
+fn text(row: &Row, index: usize) -> Value {
+    row.get::<&str, _>(index)
+        .map(Value::from)
+        .unwrap_or(Value::Null)
+}
+
+fn flag(row: &Row, index: usize) -> Value {
+    row.get::<bool, _>(index)
+        .map(Value::from)
+        .unwrap_or(Value::Null)
+}
+
+fn roles_of(roles: &[(String, String)], user_id: &Value) -> Value {
+    Value::Array(
+        roles
+            .iter()
+            .filter(|(id, _)| Some(id.as_str()) == user_id.as_str())
+            .map(|(_, name)| Value::from(name.as_str()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn searches_emails_literally_except_for_stars() {
+        assert_eq!(like_pattern("spinit"), "%spinit%");
+        assert_eq!(like_pattern("a_b@x"), r"%a\_b@x%");
+        assert_eq!(like_pattern("100%[x]"), r"%100\%\[x]%");
+        assert_eq!(like_pattern(r"a\b"), r"%a\\b%");
+        assert_eq!(like_pattern("pontus*@spinit.se"), "%pontus%@spinit.se%");
+    }
+}
@@ -0,0 +1,499 @@
+use std::{collections::HashSet, path::Path};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    commands::{
+        create_user::{self, NewUser, UserOptions, ALL_FEATURES_ROLE_ID, NEW_USER_TABLES},
+        or_exit, roles,
+    },
+    config,
+    db::{self, Connection, SqlClient},
+    output::{self, Format},
+    schema,
+};
+
+/// One user in the import file. CSV files use the same names as header columns, with the
+/// roles separated by `|`.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct ImportRow {
+    name: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    email: String,
+    username: Option<String>,
+    timezone: Option<String>,
+    locale: Option<String>,
+    office_id: Option<String>,
+    department_id: Option<String>,
+    roles: Vec<String>,
+}
+
+impl ImportRow {
+    fn options(&self) -> UserOptions {
+        UserOptions {
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            username: self.username.clone(),
+            timezone: self.timezone.clone(),
+            locale: self.locale.clone(),
+            office_id: self.office_id.clone(),
+            department_id: self.department_id.clone(),
+        }
+    }
+}
+
+struct ValidRow {
+    user: NewUser,
+    /// What `--update` writes to an existing user
+    update: UserUpdate,
+    /// Empty when the row names no roles
+    role_ids: Vec<String>,
+}
+
+pub(crate) async fn invoke(
+    file: &Path,
+    update: bool,
+    format: Format,
+    connection: &Connection,
+    profile: Option<&str>,
+) {
+    let rows = match read_rows(file) {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let defaults = or_exit(config::load(), "Could not load config").user_defaults(profile);
+
+    // validate everything before touching the database
+    let mut users = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen_emails = HashSet::new();
+    for (i, row) in rows.iter().enumerate() {
+        match NewUser::new(&row.name, &row.email, &row.options(), &defaults) {
+            Ok(_) if !seen_emails.insert(row.email.to_lowercase()) => {
+                errors.push(report_row(i, &row.email, "Duplicate email in file"))
+            }
+            Ok(user) => users.push((i, user, row)),
+            Err(e) => errors.push(report_row(i, &row.email, &e)),
+        }
+    }
+    if !errors.is_empty() {
+        output::print_rows(&["Row", "Email", "Result"], &errors, format);
+        eprintln!("Nothing was imported, fix the rows above and try again");
+        std::process::exit(1);
+    }
+
+    let connection = or_exit(connection.resolve(), "Could not resolve connection string");
+    let mut client = or_exit(
+        db::init_client(&connection).await,
+        "Could not connect to database",
+    );
+    if let Err(e) = schema::verify(&mut client, &NEW_USER_TABLES).await {
+        eprintln!("{}\nNothing was imported", e);
+        std::process::exit(1);
+    }
+
+    let all_roles = or_exit(roles::fetch_roles(&mut client).await, "Could not get roles");
+    let mut valid_rows = Vec::new();
+    for (i, user, row) in users {
+        let role_ids = roles::match_roles(&all_roles, &row.roles)
+            .map(|matched| matched.iter().map(|role| role.id.clone()).collect());
+        match role_ids {
+            Ok(role_ids) => {
+                let update = UserUpdate::new(row, &user);
+                valid_rows.push((
+                    i,
+                    ValidRow {
+                        user,
+                        update,
+                        role_ids,
+                    },
+                ))
+            }
+            Err(e) => errors.push(report_row(i, &user.email, &e.replace('\n', " "))),
+        }
+    }
+    if !errors.is_empty() {
+        output::print_rows(&["Row", "Email", "Result"], &errors, format);
+        eprintln!("Nothing was imported, fix the rows above and try again");
+        std::process::exit(1);
+    }
+
+    or_exit(
+        db::begin_transaction(&mut client).await,
+        "Could not begin transaction",
+    );
+    let result = import_rows(&mut client, &valid_rows, update).await;
+    or_exit(
+        db::end_transaction(&mut client, result.is_ok()).await,
+        "Could not end transaction",
+    );
+
+    match result {
+        Ok(report) => output::print_rows(&["Row", "Email", "Result"], &report, format),
+        Err((i, e)) => {
+            eprintln!(
+                "Row {} ({}) failed, nothing was imported: {}",
+                i + 1,
+                valid_rows
+                    .iter()
+                    .find(|(row, _)| *row == i)
+                    .map(|(_, row)| row.user.email.as_str())
+                    .unwrap_or_default(),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Creates, updates or skips every row. Fails with the index of the failing row.
+async fn import_rows(
+    client: &mut SqlClient,
+    rows: &[(usize, ValidRow)],
+    update: bool,
+) -> Result<Vec<Vec<Value>>, (usize, tiberius::error::Error)> {
+    let mut report = Vec::new();
+    for (i, row) in rows {
+        let result = import_row(client, row, update).await.map_err(|e| (*i, e))?;
+        report.push(report_row(*i, &row.user.email, result));
+    }
+    Ok(report)
+}
+
+async fn import_row(
+    client: &mut SqlClient,
+    row: &ValidRow,
+    update: bool,
+) -> Result<&'static str, tiberius::error::Error> {
+    let exists = create_user::user_with_email_exists(client, &row.user.email).await?;
+    if exists && !update {
+        return Ok("Skipped, already exists");
+    }
+
+    if exists {
+        let user_id = update_user(client, &row.user.email, &row.update).await?;
+        // an empty roles column leaves the roles alone, it never grants ALL FEATURES
+        if !row.role_ids.is_empty() {
+            remove_roles(client, &user_id).await?;
+            add_roles(client, &user_id, &row.role_ids).await?;
+        }
+        return Ok("Updated");
+    }
+
+    let user_id = create_user::insert_user(client, &row.user).await?;
+    if row.role_ids.is_empty() {
+        add_roles(client, &user_id, &[ALL_FEATURES_ROLE_ID.to_string()]).await?;
+    } else {
+        add_roles(client, &user_id, &row.role_ids).await?;
+    }
+    Ok("Created")
+}
+
+async fn add_roles(
+    client: &mut SqlClient,
+    user_id: &str,
+    role_ids: &[String],
+) -> Result<(), tiberius::error::Error> {
+    for role_id in role_ids {
+        create_user::add_role_to_user(client, user_id, role_id).await?;
+    }
+    Ok(())
+}
+
+/// Updates the fields of an existing user, returning the user id. The fields that are
+/// `None` keep their stored value.
+async fn update_user(
+    client: &mut SqlClient,
+    email: &str,
+    update: &UserUpdate,
+) -> Result<String, tiberius::error::Error> {
+    let query = r#"UPDATE [dbo].[User]
+        SET [FirstName] = COALESCE(@P2, [FirstName])
+            ,[LastName] = COALESCE(@P3, [LastName])
+            ,[Username] = COALESCE(@P4, [Username])
+            ,[Timezone] = COALESCE(@P5, [Timezone])
+            ,[Locale] = COALESCE(@P6, [Locale])
+            ,[OfficeId] = COALESCE(@P7, [OfficeId])
+            ,[DepartmentId] = COALESCE(@P8, [DepartmentId])
+        OUTPUT CONVERT(nvarchar(36), inserted.[Id])
+        WHERE [Email] = @P1"#;
+    let row = client
+        .query(
+            query,
+            &[
+                &email,
+                &update.first_name,
+                &update.last_name,
+                &update.username,
+                &update.timezone,
+                &update.locale,
+                &update.office_id,
+                &update.department_id,
+            ],
+        )
+        .await?
+        .into_row()
+        .await?;
+    Ok(row
+        .and_then(|row| row.get::<&str, _>(0).map(|id| id.to_string()))
+        .unwrap_or_default())
+}
+
+async fn remove_roles(client: &mut SqlClient, user_id: &str) -> Result<(), tiberius::error::Error> {
+    let query = r#"DELETE FROM [dbo].[UsersToRoles]
+        WHERE [UserId] = @P1"#;
+    client.execute(query, &[&user_id]).await?;
+    Ok(())
+}
+
+/// The fields a row gives for an existing user. Columns the file leaves out, or leaves
+/// empty, are `None` instead of the defaults a new user would get.
+#[derive(Debug, PartialEq)]
+struct UserUpdate {
+    first_name: Option<String>,
+    last_name: Option<String>,
+    username: Option<String>,
+    timezone: Option<String>,
+    locale: Option<String>,
+    office_id: Option<String>,
+    department_id: Option<String>,
+}
+
+impl UserUpdate {
+    fn new(row: &ImportRow, user: &NewUser) -> Self {
+        let has_name = !row.name.trim().is_empty();
+        UserUpdate {
+            first_name: (has_name || row.first_name.is_some()).then(|| user.first_name.clone()),
+            last_name: (has_name || row.last_name.is_some()).then(|| user.last_name.clone()),
+            username: row.username.as_ref().map(|_| user.username.clone()),
+            timezone: row.timezone.as_ref().map(|_| user.timezone.clone()),
+            locale: row.locale.as_ref().map(|_| user.locale.clone()),
+            office_id: row.office_id.as_ref().and(user.office_id.clone()),
+            department_id: row.department_id.as_ref().and(user.department_id.clone()),
+        }
+    }
+}
+
+fn read_rows(file: &Path) -> Result<Vec<ImportRow>, String> {
+    let content = std::fs::read_to_string(file)
+        .map_err(|e| format!("Could not read {}: {}", file.display(), e))?;
+
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid JSON in {}: {}", file.display(), e)),
+        Some("csv") => parse_csv(&content),
+        _ => Err(format!("{} is not a .csv or .json file", file.display())),
+    }
+}
+
+/// Parses CSV with a header row, separated by `,` or `;` (what Excel uses with Swedish
+/// settings). Quoted fields may contain separators, newlines and `""` escaped quotes.
+fn parse_csv(content: &str) -> Result<Vec<ImportRow>, String> {
+    let content = content.trim_start_matches('\u{feff}');
+    let header_line = content.lines().next().unwrap_or_default();
+    let separator = if header_line.contains(';') && !header_line.contains(',') {
+        ';'
+    } else {
+        ','
+    };
+
+    let mut records = split_csv(content, separator).into_iter();
+    let header = records
+        .next()
+        .ok_or_else(|| "The CSV file is empty".to_string())?
+        .iter()
+        .map(|column| column.trim().to_lowercase())
+        .collect::<Vec<_>>();
+    if !header.iter().any(|column| column == "email") {
+        return Err("The CSV file has no email column".to_string());
+    }
+
+    let optional = |value: &str| {
+        let value = value.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    };
+    records
+        .filter(|record| record.iter().any(|field| !field.trim().is_empty()))
+        .map(|record| {
+            let mut row = ImportRow::default();
+            for (column, value) in header.iter().zip(&record) {
+                match column.as_str() {
+                    "name" => row.name = value.trim().to_string(),
+                    "first_name" => row.first_name = optional(value),
+                    "last_name" => row.last_name = optional(value),
+                    "email" => row.email = value.trim().to_string(),
+                    "username" => row.username = optional(value),
+                    "timezone" => row.timezone = optional(value),
+                    "locale" => row.locale = optional(value),
+                    "office_id" => row.office_id = optional(value),
+                    "department_id" => row.department_id = optional(value),
+                    "roles" => {
+                        row.roles = value.split('|').filter_map(optional).collect::<Vec<_>>()
+                    }
+                    other => return Err(format!("Unknown CSV column {}", other)),
+                }
+            }
+            Ok(row)
+        })
+        .collect()
+}
+
+fn split_csv(content: &str, separator: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    records
+}
+
+fn report_row(index: usize, email: &str, result: &str) -> Vec<Value> {
+    vec![
+        Value::from(index + 1),
+        Value::from(email),
+        Value::from(result),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_quoted_fields() {
+        let records = split_csv(
+            "name,email\r\n\"Backman, Pontus\",pontus@spinit.se\n\"Two\nLines\",x@y\n",
+            ',',
+        );
+
+        assert_eq!(
+            records,
+            [
+                vec!["name", "email"],
+                vec!["Backman, Pontus", "pontus@spinit.se"],
+                vec!["Two\nLines", "x@y"],
+            ]
+        );
+    }
+
+    #[test]
+    fn unescapes_embedded_quotes() {
+        let records = split_csv("name\n\"Pontus \"\"Ponne\"\" Backman\"", ',');
+
+        assert_eq!(records[1], ["Pontus \"Ponne\" Backman"]);
+    }
+
+    #[test]
+    fn parses_semicolon_separated_files() {
+        let rows = parse_csv(
+            "\u{feff}Name;Email;Roles;Locale\nPontus Backman;pontus@spinit.se;Planner | Read Only;\n;;;\n",
+        )
+        .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].name, "Pontus Backman");
+        assert_eq!(rows[0].email, "pontus@spinit.se");
+        assert_eq!(rows[0].roles, ["Planner", "Read Only"]);
+        assert_eq!(rows[0].locale, None);
+    }
+
+    #[test]
+    fn leaves_roles_empty_when_the_column_is() {
+        let rows = parse_csv("name,email,roles\nPontus,pontus@spinit.se,\n").unwrap();
+
+        assert!(rows[0].roles.is_empty());
+    }
+
+    #[test]
+    fn header_only_file_has_no_rows() {
+        assert!(parse_csv("name,email\n").unwrap().is_empty());
+        assert!(parse_csv("name,email").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_files_without_an_email_column_or_with_unknown_columns() {
+        assert_eq!(parse_csv("").unwrap_err(), "The CSV file is empty");
+        assert_eq!(
+            parse_csv("name\nPontus\n").unwrap_err(),
+            "The CSV file has no email column"
+        );
+        assert_eq!(
+            parse_csv("email,phone\na@b,123\n").unwrap_err(),
+            "Unknown CSV column phone"
+        );
+    }
+
+    #[test]
+    fn update_keeps_the_columns_the_file_leaves_out() {
+        let defaults = config::UserDefaults {
+            office_id: Some("default-office".to_string()),
+            ..Default::default()
+        };
+        let rows =
+            parse_csv("name,email,locale,timezone\nPontus Backman,pontus@spinit.se,en-GB,\n")
+                .unwrap();
+        let user =
+            NewUser::new(&rows[0].name, &rows[0].email, &rows[0].options(), &defaults).unwrap();
+
+        assert_eq!(
+            UserUpdate::new(&rows[0], &user),
+            UserUpdate {
+                first_name: Some("Pontus".to_string()),
+                last_name: Some("Backman".to_string()),
+                username: None,
+                timezone: None,
+                locale: Some("en-GB".to_string()),
+                office_id: None,
+                department_id: None,
+            }
+        );
+    }
+
+    #[test]
+    fn update_without_a_name_keeps_the_stored_name() {
+        let rows = parse_csv("email,office_id\npontus@spinit.se,office\n").unwrap();
+        let user = NewUser {
+            first_name: "x".to_string(),
+            last_name: String::new(),
+            email: rows[0].email.clone(),
+            username: rows[0].email.clone(),
+            timezone: String::new(),
+            locale: String::new(),
+            office_id: Some("office".to_string()),
+            department_id: None,
+        };
+
+        let update = UserUpdate::new(&rows[0], &user);
+
+        assert_eq!(update.first_name, None);
+        assert_eq!(update.last_name, None);
+        assert_eq!(update.office_id, Some("office".to_string()));
+    }
+}
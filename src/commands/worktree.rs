@@ -0,0 +1,340 @@
+use std::path::{Component, Path, PathBuf};
+
+use console::style;
+
+use crate::{
+    commands,
+    git::{self, find_git_modules, GitModule},
+    platform,
+};
+
+pub(crate) struct Reapply {
+    pub connection_strings: Option<(String, String)>,
+    pub application_host: bool,
+}
+
+pub(crate) fn add(dir: &Path, branch: &str, reapply: &Reapply, root_path: &Path) {
+    let dir = absolute(dir);
+    if dir.exists() {
+        eprintln!("{} already exists", dir.display());
+        return;
+    }
+    // the bulk commands walk everything below the root, they would find the new tree as well
+    if dir.starts_with(absolute(root_path)) {
+        eprintln!(
+            "{} is inside {}, put the worktree next to it instead, e.g. ../{}-review",
+            dir.display(),
+            root_path.display(),
+            root_name(root_path)
+        );
+        return;
+    }
+
+    let target = git::resolve_branch(root_path, branch);
+    if !add_worktree(root_path, &dir, &target) {
+        println!(
+            "❌ - failed to create worktree of {} at {}",
+            style(root_name(root_path)).bold().red(),
+            dir.display()
+        );
+        return;
+    }
+    println!(
+        "✅ - checked out {} of {} at {}",
+        style(&target).bold().dim(),
+        style(root_name(root_path)).bold(),
+        dir.display()
+    );
+
+    // parents first, nested submodules live inside their parent's worktree
+    for (module, relative_path) in submodules(root_path) {
+        let target = git::resolve_branch(&module.path, branch);
+        if add_worktree(&module.path, &dir.join(&relative_path), &target) {
+            println!(
+                "✅ - checked out {} of {}",
+                style(&target).bold().dim(),
+                style(&module.name).bold()
+            );
+        } else {
+            println!(
+                "❌ - failed to create worktree of {}",
+                style(&module.name).bold().red()
+            );
+        }
+    }
+
+    if let Some((main, service_bus)) = &reapply.connection_strings {
+        commands::connection_strings::invoke(
+            main,
+            service_bus,
+            false,
+            &dir,
+            platform::native().as_ref(),
+        );
+    }
+    if reapply.application_host {
+        let source = root_path.join(commands::application_host::APPHOST_PATH);
+        let destination = dir.join(commands::application_host::APPHOST_PATH);
+        if source.exists() && !destination.exists() {
+            std::fs::create_dir_all(destination.parent().expect("Path has no parent"))
+                .expect("Could not create .vs directory");
+            std::fs::copy(&source, &destination).expect("Could not copy applicationhost.config");
+        }
+        commands::application_host::invoke(&dir);
+    }
+}
+
+pub(crate) fn remove(dir: &Path, force: bool, root_path: &Path) {
+    let dir = absolute(dir);
+    let mut worktrees = submodules(root_path)
+        .into_iter()
+        .map(|(module, relative_path)| (module, dir.join(relative_path)))
+        .filter(|(_, worktree_path)| worktree_path.join(".git").exists())
+        .collect::<Vec<_>>();
+    // children before their parents
+    worktrees.reverse();
+
+    if !force {
+        let dirty = worktrees
+            .iter()
+            .map(|(module, worktree_path)| GitModule {
+                path: worktree_path.clone(),
+                name: module.name.clone(),
+            })
+            .chain(std::iter::once(GitModule {
+                path: dir.clone(),
+                name: root_name(root_path),
+            }))
+            .filter(|worktree| git::is_dirty(worktree, true))
+            .collect::<Vec<_>>();
+        if !dirty.is_empty() {
+            eprintln!("The following worktrees have changes, use --force to remove them anyway:");
+            for worktree in dirty {
+                eprintln!("  {}", style(&worktree.name).bold().red());
+            }
+            return;
+        }
+    }
+
+    for (module, worktree_path) in worktrees {
+        if remove_worktree(&module.path, &worktree_path, force) {
+            println!("✅ - removed worktree of {}", style(&module.name).bold());
+        } else {
+            println!(
+                "❌ - failed to remove worktree of {}",
+                style(&module.name).bold().red()
+            );
+        }
+    }
+
+    // the removed submodule worktrees show up as changes in the superproject, so force it
+    if remove_worktree(root_path, &dir, true) {
+        println!(
+            "✅ - removed worktree of {} at {}",
+            style(root_name(root_path)).bold(),
+            dir.display()
+        );
+    } else {
+        println!(
+            "❌ - failed to remove worktree of {}",
+            style(root_name(root_path)).bold().red()
+        );
+    }
+}
+
+/// All repositories below `root_path` except the root itself, sorted so parents come first.
+fn submodules(root_path: &Path) -> Vec<(GitModule, PathBuf)> {
+    let mut modules = find_git_modules(root_path)
+        .into_iter()
+        .filter_map(|module| {
+            let relative_path = module.path.strip_prefix(root_path).ok()?.to_path_buf();
+            if relative_path.as_os_str().is_empty() {
+                None
+            } else {
+                Some((module, relative_path))
+            }
+        })
+        .collect::<Vec<_>>();
+    modules.sort_by_key(|(_, relative_path)| relative_path.components().count());
+    modules
+}
+
+fn add_worktree(repo_path: &Path, worktree_path: &Path, target: &str) -> bool {
+    std::process::Command::new("git")
+        .arg("worktree")
+        .arg("add")
+        .arg("--detach")
+        .arg(worktree_path)
+        .arg(target)
+        .current_dir(repo_path)
+        .output()
+        .expect("Failed to run git command")
+        .status
+        .success()
+}
+
+fn remove_worktree(repo_path: &Path, worktree_path: &Path, force: bool) -> bool {
+    let mut command = std::process::Command::new("git");
+    command.arg("worktree").arg("remove");
+    if force {
+        command.arg("--force");
+    }
+    command
+        .arg(worktree_path)
+        .current_dir(repo_path)
+        .output()
+        .expect("Failed to run git command")
+        .status
+        .success()
+}
+
+/// Makes `path` absolute and drops its `.` and `..` components, without touching the disk
+/// since the path may not exist yet.
+fn absolute(path: &Path) -> PathBuf {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .expect("Could not get current directory")
+            .join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn root_name(root_path: &Path) -> String {
+    root_path
+        .file_name()
+        .unwrap_or(root_path.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("Failed to run git command");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    fn init_repo(path: &Path) {
+        std::fs::create_dir_all(path).unwrap();
+        std::fs::write(path.join("README.md"), "readme").unwrap();
+        git(path, &["init", "--quiet"]);
+        git(path, &["add", "."]);
+        git(
+            path,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "--quiet",
+                "-m",
+                "init",
+            ],
+        );
+    }
+
+    fn worktrees(repo_path: &Path) -> Vec<PathBuf> {
+        git(repo_path, &["worktree", "list", "--porcelain"])
+            .lines()
+            .filter_map(|line| line.strip_prefix("worktree "))
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    const NO_REAPPLY: Reapply = Reapply {
+        connection_strings: None,
+        application_host: false,
+    };
+
+    #[test]
+    fn adds_and_removes_worktrees_of_every_repository() {
+        let temp = std::env::temp_dir().join(format!("tainer-worktree-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp);
+        let root = temp.join("ELOS");
+        init_repo(&root);
+        init_repo(&temp.join("Api"));
+        git(
+            &root,
+            &[
+                "-c",
+                "protocol.file.allow=always",
+                "submodule",
+                "add",
+                "--quiet",
+                "../Api",
+            ],
+        );
+        git(
+            &root,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "--quiet",
+                "-m",
+                "api",
+            ],
+        );
+        let review = temp.join("ELOS-review");
+
+        add(&review, "main", &NO_REAPPLY, &root);
+        let root_worktrees = worktrees(&root);
+        let api_worktrees = worktrees(&root.join("Api"));
+        let modules = find_git_modules(&root).len();
+        remove(&review, false, &root);
+        let removed = !review.exists() && worktrees(&root).len() == 1;
+        std::fs::remove_dir_all(&temp).unwrap();
+
+        assert_eq!(root_worktrees.len(), 2);
+        assert!(root_worktrees[1].ends_with("ELOS-review"));
+        assert_eq!(api_worktrees.len(), 2);
+        assert!(api_worktrees[1].ends_with("ELOS-review/Api"));
+        assert_eq!(modules, 2);
+        assert!(removed);
+    }
+
+    #[test]
+    fn refuses_a_worktree_inside_the_root() {
+        let temp =
+            std::env::temp_dir().join(format!("tainer-worktree-inside-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp);
+        init_repo(&temp);
+
+        add(&temp.join("review"), "main", &NO_REAPPLY, &temp);
+        let worktrees = worktrees(&temp);
+        std::fs::remove_dir_all(&temp).unwrap();
+
+        assert_eq!(worktrees.len(), 1);
+    }
+
+    #[test]
+    fn drops_dot_components() {
+        assert_eq!(
+            absolute(Path::new("/src/ELOS/./../ELOS-review")),
+            Path::new("/src/ELOS-review")
+        );
+    }
+}
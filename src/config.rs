@@ -0,0 +1,159 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::{db::Encrypt, dirs};
+
+static CONFIG_FILE: &str = "config.toml";
+
+/// Settings from `~/.tainer/config.toml`, every section is optional.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub user: UserDefaults,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named database connection, e.g. `[profiles.local]`, picked with `--profile`.
+#[derive(Deserialize, Clone)]
+pub struct Profile {
+    pub connection_string: String,
+    /// Accept the server certificate without validating it
+    #[serde(default)]
+    pub trust_server_cert: bool,
+    /// CA certificate to validate the server certificate against
+    pub ca_file: Option<PathBuf>,
+    pub encrypt: Option<Encrypt>,
+    /// `[profiles.<name>.user]`, overrides `[user]` for the users created through the profile
+    #[serde(default)]
+    pub user: ProfileUserDefaults,
+}
+
+/// Defaults for new users, used when the matching `create-user` flag isn't given.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct UserDefaults {
+    /// Username template, `{email}` and `{user}` (the part before the @) are replaced.
+    /// The email is used when there is none.
+    pub username: Option<String>,
+    pub timezone: String,
+    pub locale: String,
+    pub office_id: Option<String>,
+    pub department_id: Option<String>,
+}
+
+impl Default for UserDefaults {
+    fn default() -> Self {
+        UserDefaults {
+            username: None,
+            timezone: "W. Europe Standard Time".to_string(),
+            locale: "sv-SE".to_string(),
+            office_id: None,
+            department_id: None,
+        }
+    }
+}
+
+impl UserDefaults {
+    pub fn username_for(&self, email: &str) -> String {
+        match &self.username {
+            Some(template) => template
+                .replace("{email}", email)
+                .replace("{user}", email.split('@').next().unwrap_or(email)),
+            None => email.to_string(),
+        }
+    }
+}
+
+/// The `[user]` fields a profile sets, the others come from `[user]`.
+#[derive(Deserialize, Default, Clone)]
+pub struct ProfileUserDefaults {
+    pub username: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub office_id: Option<String>,
+    pub department_id: Option<String>,
+}
+
+impl Config {
+    /// The defaults for new users, with those of the profile (if any) over `[user]`.
+    pub fn user_defaults(&self, profile: Option<&str>) -> UserDefaults {
+        let Some(overrides) = profile
+            .and_then(|name| self.profiles.get(name))
+            .map(|profile| &profile.user)
+        else {
+            return self.user.clone();
+        };
+        UserDefaults {
+            username: overrides
+                .username
+                .clone()
+                .or_else(|| self.user.username.clone()),
+            timezone: overrides
+                .timezone
+                .clone()
+                .unwrap_or_else(|| self.user.timezone.clone()),
+            locale: overrides
+                .locale
+                .clone()
+                .unwrap_or_else(|| self.user.locale.clone()),
+            office_id: overrides
+                .office_id
+                .clone()
+                .or_else(|| self.user.office_id.clone()),
+            department_id: overrides
+                .department_id
+                .clone()
+                .or_else(|| self.user.department_id.clone()),
+        }
+    }
+}
+
+pub fn load() -> Result<Config, String> {
+    let path = dirs::tainer_dir().join(CONFIG_FILE);
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| format!("Invalid config in {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_user_defaults_override_the_global_ones() {
+        let config: Config = toml::from_str(
+            r#"
+            [user]
+            username = "{user}"
+            locale = "en-GB"
+            office_id = "stockholm"
+
+            [profiles.test]
+            connection_string = "Server=test-sql;Database=dbEnvirotainerELOS;"
+
+            [profiles.test.user]
+            locale = "nb-NO"
+            department_id = "qa"
+            "#,
+        )
+        .unwrap();
+
+        let global = config.user_defaults(None);
+        let test = config.user_defaults(Some("test"));
+        let unknown = config.user_defaults(Some("other"));
+
+        assert_eq!(global.locale, "en-GB");
+        assert_eq!(global.department_id, None);
+        assert_eq!(test.locale, "nb-NO");
+        assert_eq!(test.department_id.as_deref(), Some("qa"));
+        assert_eq!(test.office_id.as_deref(), Some("stockholm"));
+        assert_eq!(test.timezone, "W. Europe Standard Time");
+        assert_eq!(test.username_for("pontus@spinit.se"), "pontus");
+        assert_eq!(unknown.locale, "en-GB");
+    }
+}
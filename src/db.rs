@@ -0,0 +1,541 @@
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+use tiberius::{AuthMethod, Client, Config, EncryptionLevel};
+use tokio::net::TcpStream;
+use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+
+use crate::{
+    browser::{self, PortCache},
+    config::{self, Profile},
+    vault,
+};
+
+static DEFAULT_PROFILE: &str = "default";
+
+/// Environment variable holding the access token for `--aad-token`.
+pub static AAD_TOKEN_ENV: &str = "TAINER_AAD_TOKEN";
+
+/// Connection string keys replaced by the TLS options.
+static TRUST_KEYS: [&str; 2] = ["trustservercertificate", "trustservercertificateca"];
+static ENCRYPT_KEYS: [&str; 1] = ["encrypt"];
+
+pub type SqlClient = Client<Compat<TcpStream>>;
+
+#[derive(Args, Clone)]
+pub struct ConnectionArgs {
+    /// Connection string of the database (dbEnvirotainerELOS)
+    #[clap(short, long, conflicts_with = "profile")]
+    pub connection_string: Option<String>,
+    /// Named connection profile from ~/.tainer/config.toml [default: default]
+    #[clap(long)]
+    pub profile: Option<String>,
+    /// Log in as this SQL Server user instead of the login in the connection string
+    #[clap(long, requires = "sql_password", conflicts_with = "aad_token")]
+    pub sql_user: Option<String>,
+    /// Password for --sql-user, supports ${secret:<name>}
+    #[clap(long, requires = "sql_user")]
+    pub sql_password: Option<String>,
+    /// Log in with the Azure AD access token in the TAINER_AAD_TOKEN environment variable
+    #[clap(long)]
+    pub aad_token: bool,
+    /// Accept the server certificate without validating it, e.g. a self-signed one
+    #[clap(long, conflicts_with = "ca_file")]
+    pub trust_server_cert: bool,
+    /// Validate the server certificate against this CA certificate
+    #[clap(long)]
+    pub ca_file: Option<PathBuf>,
+    /// Encryption of the connection, off only encrypts the login
+    #[clap(long, value_enum)]
+    pub encrypt: Option<Encrypt>,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encrypt {
+    Off,
+    On,
+    Required,
+}
+
+impl From<Encrypt> for EncryptionLevel {
+    fn from(encrypt: Encrypt) -> Self {
+        match encrypt {
+            Encrypt::Off => EncryptionLevel::Off,
+            Encrypt::On => EncryptionLevel::On,
+            Encrypt::Required => EncryptionLevel::Required,
+        }
+    }
+}
+
+/// TLS settings that replace the ones in the connection string.
+#[derive(Clone, Default)]
+pub struct Tls {
+    pub trust_server_cert: bool,
+    pub ca_file: Option<PathBuf>,
+    pub encrypt: Option<Encrypt>,
+}
+
+/// A login that replaces the one in the connection string.
+#[derive(Clone)]
+pub enum Login {
+    Sql { user: String, password: String },
+    AadToken(String),
+}
+
+/// A connection string, with the login and TLS settings that replace the ones in it.
+pub struct Connection {
+    pub connection_string: String,
+    pub login: Option<Login>,
+    pub tls: Tls,
+}
+
+impl Connection {
+    /// The connection with the `${secret:<name>}` placeholders in its connection string
+    /// replaced by their values.
+    pub fn resolve(&self) -> Result<Connection, String> {
+        Ok(Connection {
+            connection_string: vault::resolve(&self.connection_string)?,
+            login: self.login.clone(),
+            tls: self.tls.clone(),
+        })
+    }
+
+    /// The same login and TLS settings for another connection string.
+    pub fn with_connection_string(&self, connection_string: &str) -> Connection {
+        Connection {
+            connection_string: connection_string.to_string(),
+            login: self.login.clone(),
+            tls: self.tls.clone(),
+        }
+    }
+}
+
+impl ConnectionArgs {
+    /// The same options, with a connection string given as a positional argument instead of
+    /// `--connection-string`.
+    pub fn with_connection_string(&self, connection_string: &Option<String>) -> ConnectionArgs {
+        ConnectionArgs {
+            connection_string: connection_string
+                .clone()
+                .or_else(|| self.connection_string.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// The connection string, login and TLS settings given on the command line, or the
+    /// profile's.
+    pub fn connection(&self) -> Result<Connection, String> {
+        Ok(Connection {
+            connection_string: self.connection_string()?,
+            login: self.login()?,
+            tls: self.tls()?,
+        })
+    }
+
+    /// Like `connection`, but with the connection string from `ask` if there is neither one on
+    /// the command line nor a profile to take it from.
+    pub fn connection_or_else(&self, ask: impl FnOnce() -> String) -> Result<Connection, String> {
+        let connection_string = match (&self.connection_string, self.profile()?) {
+            (None, None) => ask(),
+            _ => self.connection_string()?,
+        };
+        Ok(Connection {
+            connection_string,
+            login: self.login()?,
+            tls: self.tls()?,
+        })
+    }
+
+    /// The connection string given on the command line, or the one from the profile.
+    pub fn connection_string(&self) -> Result<String, String> {
+        match &self.connection_string {
+            Some(connection_string) => Ok(connection_string.clone()),
+            None => self.profile()?.map(|profile| profile.connection_string).ok_or_else(|| {
+                "No connection string given, use --connection-string or add a [profiles.default] to ~/.tainer/config.toml"
+                    .to_string()
+            }),
+        }
+    }
+
+    /// The TLS options from the command line, falling back to the ones in the profile.
+    pub fn tls(&self) -> Result<Tls, String> {
+        let mut tls = self
+            .profile()?
+            .map(|profile| Tls {
+                trust_server_cert: profile.trust_server_cert,
+                ca_file: profile.ca_file,
+                encrypt: profile.encrypt,
+            })
+            .unwrap_or_default();
+        if self.trust_server_cert || self.ca_file.is_some() {
+            tls.trust_server_cert = self.trust_server_cert;
+            tls.ca_file = self.ca_file.clone();
+        }
+        if self.encrypt.is_some() {
+            tls.encrypt = self.encrypt;
+        }
+
+        if tls.trust_server_cert && tls.ca_file.is_some() {
+            return Err(
+                "trust_server_cert and ca_file can't be combined, use one of them".to_string(),
+            );
+        }
+        Ok(tls)
+    }
+
+    /// The profile the options come from, none if a connection string is given or there is no
+    /// default profile.
+    fn profile(&self) -> Result<Option<Profile>, String> {
+        let Some(name) = self.profile_name() else {
+            return Ok(None);
+        };
+        let config = config::load()?;
+        match config.profiles.get(name) {
+            Some(profile) => Ok(Some(profile.clone())),
+            None if self.profile.is_none() => Ok(None),
+            None => Err(format!(
+                "No profile named {} in ~/.tainer/config.toml, available profiles: {}",
+                name,
+                config
+                    .profiles
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// The profile the connection comes from: `--profile`, or `default` without `-c`.
+    pub fn profile_name(&self) -> Option<&str> {
+        if self.connection_string.is_some() {
+            return None;
+        }
+        Some(self.profile.as_deref().unwrap_or(DEFAULT_PROFILE))
+    }
+
+    /// The login given with `--sql-user` or `--aad-token`, if any.
+    pub fn login(&self) -> Result<Option<Login>, String> {
+        if let (Some(user), Some(password)) = (&self.sql_user, &self.sql_password) {
+            let password = vault::resolve(password)
+                .map_err(|e| format!("Could not resolve --sql-password: {}", e))?;
+            return Ok(Some(Login::Sql {
+                user: user.clone(),
+                password,
+            }));
+        }
+
+        if self.aad_token {
+            return match std::env::var(AAD_TOKEN_ENV) {
+                Ok(token) if !token.trim().is_empty() => {
+                    Ok(Some(Login::AadToken(token.trim().to_string())))
+                }
+                _ => Err(format!(
+                    "{} is not set, get a token with: az account get-access-token --resource https://database.windows.net --query accessToken -o tsv",
+                    AAD_TOKEN_ENV
+                )),
+            };
+        }
+
+        Ok(None)
+    }
+}
+
+/// Parses the connection string, with the connection's TLS settings replacing its own.
+pub fn parse_config(connection: &Connection) -> Result<Config, String> {
+    let tls = &connection.tls;
+    let mut connection_string = connection.connection_string.clone();
+    if tls.trust_server_cert || tls.ca_file.is_some() {
+        connection_string = without_keys(&connection_string, &TRUST_KEYS);
+    }
+    if tls.encrypt.is_some() {
+        connection_string = without_keys(&connection_string, &ENCRYPT_KEYS);
+    }
+
+    // tiberius panics on this combination instead of returning an error
+    if value_of(&connection_string, &TRUST_KEYS[..1])
+        .is_some_and(|value| matches!(value.to_lowercase().as_str(), "true" | "yes"))
+        && value_of(&connection_string, &TRUST_KEYS[1..]).is_some()
+    {
+        return Err(
+            "TrustServerCertificate and TrustServerCertificateCA can't be combined, use one of them"
+                .to_string(),
+        );
+    }
+
+    let mut config = Config::from_ado_string(&connection_string)
+        .map_err(|e| format!("Invalid connection string: {}", e))?;
+    if tls.trust_server_cert {
+        config.trust_cert();
+    }
+    if let Some(ca_file) = &tls.ca_file {
+        if !ca_file.is_file() {
+            return Err(format!("CA file {} does not exist", ca_file.display()));
+        }
+        let extension = ca_file
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        if !matches!(extension.as_deref(), Some("pem" | "crt" | "der")) {
+            return Err(format!(
+                "CA file {} should be a .pem, .crt or .der certificate",
+                ca_file.display()
+            ));
+        }
+        config.trust_cert_ca(ca_file.display());
+    }
+    if let Some(encrypt) = tls.encrypt {
+        config.encryption(encrypt.into());
+    }
+
+    Ok(config)
+}
+
+/// Turns the TLS library's certificate errors into what to do about them.
+pub fn describe_tls_error(message: &str) -> String {
+    let lower = message.to_lowercase();
+    let advice = if lower.contains("self-signed") || lower.contains("self signed") {
+        "The server certificate is self-signed, trust it with --trust-server-cert or validate it with --ca-file"
+    } else if lower.contains("expired") {
+        "The server certificate has expired, renew it on the server or use --trust-server-cert"
+    } else if lower.contains("hostname")
+        || lower.contains("host name")
+        || lower.contains("not valid for")
+    {
+        "The server certificate is for another host name, connect with the name in the certificate"
+    } else if lower.contains("issuer")
+        || lower.contains("not trusted")
+        || lower.contains("untrusted")
+        || lower.contains("verify failed")
+    {
+        "The server certificate is not issued by a trusted CA, validate it with --ca-file or use --trust-server-cert"
+    } else {
+        "TLS negotiation failed"
+    };
+    format!("{} ({})", advice, message)
+}
+
+/// Applies the connection's login to the config and checks that it works on this platform.
+/// Returns a description of how the client logs in.
+pub fn configure_login(config: &mut Config, connection: &Connection) -> Result<String, String> {
+    match &connection.login {
+        Some(Login::Sql { user, password }) => {
+            config.authentication(AuthMethod::sql_server(user, password));
+            Ok("SQL Server login (--sql-user)".to_string())
+        }
+        Some(Login::AadToken(token)) => {
+            config.authentication(AuthMethod::aad_token(token));
+            Ok("Azure AD access token (--aad-token)".to_string())
+        }
+        None if is_integrated(&connection.connection_string) => {
+            if cfg!(windows) {
+                Ok("Windows integrated security".to_string())
+            } else {
+                Err("Integrated Security is only supported on Windows, log in with --sql-user and --sql-password, or --aad-token".to_string())
+            }
+        }
+        None => Ok("SQL Server login from the connection string".to_string()),
+    }
+}
+
+fn is_integrated(connection_string: &str) -> bool {
+    value_of(
+        connection_string,
+        &[
+            "integrated security",
+            "integratedsecurity",
+            "trusted_connection",
+        ],
+    )
+    .is_some_and(|value| matches!(value.to_lowercase().as_str(), "sspi" | "true" | "yes"))
+}
+
+pub async fn init_client(connection: &Connection) -> Result<SqlClient, tiberius::error::Error> {
+    connect(
+        parse_config(connection).map_err(invalid_config)?,
+        connection,
+    )
+    .await
+}
+
+/// Connects to the `master` database of the server in the connection string, for statements
+/// that can't run inside the database they affect.
+pub async fn init_master_client(
+    connection: &Connection,
+) -> Result<SqlClient, tiberius::error::Error> {
+    let mut config = parse_config(connection).map_err(invalid_config)?;
+    config.database("master");
+    connect(config, connection).await
+}
+
+async fn connect(
+    mut config: Config,
+    connection: &Connection,
+) -> Result<SqlClient, tiberius::error::Error> {
+    configure_login(&mut config, connection)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Unsupported, e))?;
+    let tcp = match server(&connection.connection_string) {
+        // tiberius only knows the instance name, the port has to come from the SQL Browser
+        Ok(Server {
+            host,
+            instance: Some(instance),
+            port: None,
+        }) => {
+            let (tcp, port) = connect_instance(&host, &instance).await?;
+            config.port(port);
+            tcp
+        }
+        _ => TcpStream::connect(config.get_addr()).await?,
+    };
+    tcp.set_nodelay(true)?;
+
+    let client = Client::connect(config, tcp.compat_write())
+        .await
+        .map_err(|e| match e {
+            tiberius::error::Error::Tls(message) => {
+                tiberius::error::Error::Tls(describe_tls_error(&message))
+            }
+            e => e,
+        })?;
+
+    Ok(client)
+}
+
+fn invalid_config(e: String) -> tiberius::error::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, e).into()
+}
+
+/// Connects to a named instance through the cached port, and asks the SQL Browser again if
+/// the cached port doesn't answer anymore.
+async fn connect_instance(
+    host: &str,
+    instance: &str,
+) -> Result<(TcpStream, u16), tiberius::error::Error> {
+    let mut cache = PortCache::load();
+    let lookup_error = |e: String| std::io::Error::new(std::io::ErrorKind::NotFound, e);
+
+    if let Some(port) = cache.get(host, instance) {
+        if let Ok(tcp) = TcpStream::connect((host, port)).await {
+            return Ok((tcp, port));
+        }
+        cache.forget(host, instance);
+    }
+
+    let port = browser::resolve(&mut cache, host, instance, browser::BROWSER_PORT)
+        .await
+        .map_err(lookup_error)?;
+    let tcp = TcpStream::connect((host, port)).await?;
+    // a cache that can't be written only means the next connection asks the browser again
+    let _ = cache.save();
+    Ok((tcp, port))
+}
+
+// Transactions are controlled with plain batches, running BEGIN/COMMIT through
+// sp_executesql makes SQL Server complain about mismatching transaction counts.
+pub async fn begin_transaction(client: &mut SqlClient) -> Result<(), tiberius::error::Error> {
+    client
+        .simple_query("BEGIN TRANSACTION")
+        .await?
+        .into_results()
+        .await?;
+    Ok(())
+}
+
+/// Commits the transaction if `commit` is true, rolls it back otherwise.
+pub async fn end_transaction(
+    client: &mut SqlClient,
+    commit: bool,
+) -> Result<(), tiberius::error::Error> {
+    let query = if commit {
+        "COMMIT TRANSACTION"
+    } else {
+        "ROLLBACK TRANSACTION"
+    };
+    client.simple_query(query).await?.into_results().await?;
+    Ok(())
+}
+
+/// The server part of a connection string, `[tcp:]host[\\instance][,port]`.
+pub struct Server {
+    pub host: String,
+    pub instance: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// The `Initial Catalog` (or `Database`) of a connection string.
+pub fn database_name(connection_string: &str) -> Option<String> {
+    value_of(connection_string, &["initial catalog", "database"])
+}
+
+/// The `Data Source` (or `Server`) of a connection string. `.` and `(local)` mean localhost.
+pub fn server(connection_string: &str) -> Result<Server, String> {
+    let server = value_of(
+        connection_string,
+        &[
+            "data source",
+            "server",
+            "address",
+            "addr",
+            "network address",
+        ],
+    )
+    .ok_or("The connection string has no Data Source or Server")?;
+
+    let server = server.strip_prefix("tcp:").unwrap_or(&server);
+    let (server, port) = match server.split_once(',') {
+        Some((server, port)) => {
+            let port = port
+                .trim()
+                .parse::<u16>()
+                .map_err(|_| format!("Invalid port {} in the connection string", port))?;
+            (server, Some(port))
+        }
+        None => (server, None),
+    };
+    let (host, instance) = match server.split_once('\\') {
+        Some((host, instance)) => (host, Some(instance.trim().to_string())),
+        None => (server, None),
+    };
+    let host = match host.trim() {
+        "" | "." | "(local)" => "localhost",
+        host => host,
+    };
+
+    Ok(Server {
+        host: host.to_string(),
+        instance,
+        port,
+    })
+}
+
+fn without_keys(connection_string: &str, keys: &[&str]) -> String {
+    connection_string
+        .split(';')
+        .filter(|pair| {
+            !pair
+                .split_once('=')
+                .is_some_and(|(key, _)| keys.contains(&key.trim().to_lowercase().as_str()))
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn value_of(connection_string: &str, keys: &[&str]) -> Option<String> {
+    connection_string.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        let key = key.trim().to_lowercase();
+        let value = value.trim().trim_matches(['\'', '"', '{', '}']);
+        (keys.contains(&key.as_str()) && !value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Quotes an identifier for use in dynamic SQL, like QUOTENAME in T-SQL.
+pub fn quote_name(name: &str) -> String {
+    format!("[{}]", name.replace(']', "]]"))
+}
+
+/// Quotes a string literal for use in dynamic SQL.
+pub fn quote_literal(value: &str) -> String {
+    format!("N'{}'", value.replace('\'', "''"))
+}
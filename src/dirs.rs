@@ -0,0 +1,9 @@
+use std::path::PathBuf;
+
+/// Directory for the tool's own files, `~/.tainer`.
+pub fn tainer_dir() -> PathBuf {
+    let home = std::env::var_os("USERPROFILE")
+        .or_else(|| std::env::var_os("HOME"))
+        .expect("Could not find home directory");
+    PathBuf::from(home).join(".tainer")
+}
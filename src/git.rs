@@ -74,3 +74,261 @@ pub fn init_submodules(root_path: &Path) {
 fn is_git_repo(entry: &DirEntry) -> bool {
     entry.path().join(".git").exists()
 }
+
+pub fn is_dirty(module: &GitModule, include_untracked: bool) -> bool {
+    let untracked = if include_untracked {
+        "--untracked-files=normal"
+    } else {
+        "--untracked-files=no"
+    };
+    // submodules are checked on their own, so only look at this repository's files
+    std::process::Command::new("git")
+        .args(["status", "--porcelain", "--ignore-submodules", untracked])
+        .current_dir(&module.path)
+        .output()
+        .map(|output| !output.stdout.is_empty())
+        .unwrap_or(false)
+}
+
+pub fn conflicted_files(module: &GitModule) -> Vec<String> {
+    let output = std::process::Command::new("git")
+        .args(["diff", "--name-only", "--diff-filter=U"])
+        .current_dir(&module.path)
+        .output()
+        .expect("Failed to run git command");
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Resolves `branch` to something that can be checked out in `repo_path`, preferring the
+/// local branch, then the remote one, and finally the default branch of `origin`.
+pub fn resolve_branch(repo_path: &Path, branch: &str) -> String {
+    let exists = |rev: &str| {
+        std::process::Command::new("git")
+            .args([
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{}^{{commit}}", rev),
+            ])
+            .current_dir(repo_path)
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    };
+
+    let remote_branch = format!("origin/{}", branch);
+    if exists(branch) {
+        return branch.to_string();
+    }
+    if exists(&remote_branch) {
+        return remote_branch;
+    }
+
+    std::process::Command::new("git")
+        .args([
+            "symbolic-ref",
+            "--quiet",
+            "--short",
+            "refs/remotes/origin/HEAD",
+        ])
+        .current_dir(repo_path)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "HEAD".to_string())
+}
+
+/// Returns the hooks directory of a repository, following gitlink `.git` files of submodules
+/// and worktrees as well as `core.hooksPath`.
+pub fn hooks_dir(module: &GitModule) -> Option<PathBuf> {
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .current_dir(&module.path)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    let hooks_path = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    Some(module.path.join(hooks_path))
+}
+
+pub fn is_tracked(file_path: &Path) -> bool {
+    git_for_file(file_path, &["ls-files", "--error-unmatch"])
+}
+
+/// Makes git ignore local changes to a tracked file (or stop ignoring them).
+pub fn set_skip_worktree(file_path: &Path, skip: bool) -> bool {
+    let flag = if skip {
+        "--skip-worktree"
+    } else {
+        "--no-skip-worktree"
+    };
+    git_for_file(file_path, &["update-index", flag])
+}
+
+pub fn skip_worktree_files(module: &GitModule) -> Vec<PathBuf> {
+    let output = std::process::Command::new("git")
+        .args(["ls-files", "-v"])
+        .current_dir(&module.path)
+        .output()
+        .expect("Failed to run git command");
+
+    // skip-worktree files are tagged with an 'S'
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("S "))
+        .map(|file| module.path.join(file))
+        .collect()
+}
+
+/// Sparse checkouts mark the files outside the sparse cone as skip-worktree too.
+pub fn is_sparse_checkout(module: &GitModule) -> bool {
+    std::process::Command::new("git")
+        .args(["config", "--bool", "core.sparseCheckout"])
+        .current_dir(&module.path)
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "true")
+        .unwrap_or(false)
+}
+
+/// Adds a file to the list of files tainer made skip-worktree, kept in `.git/info`, so
+/// `unprotect` leaves the ones marked by hand or by a sparse checkout alone.
+pub fn record_protected(file_path: &Path) -> bool {
+    let (Some(dir), Some(file_name)) = (file_path.parent(), file_path.file_name()) else {
+        return false;
+    };
+    let list_path = git_output(dir, &["rev-parse", "--git-path", PROTECTED_LIST]);
+    let file = git_output(
+        dir,
+        &[
+            "ls-files",
+            "--full-name",
+            "--",
+            &file_name.to_string_lossy(),
+        ],
+    );
+    let (Some(list_path), Some(file)) = (list_path.map(|path| dir.join(path)), file) else {
+        return false;
+    };
+
+    let mut files = read_protected_list(&list_path);
+    if !files.contains(&file) {
+        files.push(file);
+    }
+    write_protected_list(&list_path, &files)
+}
+
+/// Returns the files tainer protected that are still skip-worktree.
+pub fn protected_files(module: &GitModule) -> Vec<PathBuf> {
+    let skipped = skip_worktree_files(module);
+    protected_list_path(module)
+        .map(|list_path| read_protected_list(&list_path))
+        .unwrap_or_default()
+        .iter()
+        .map(|file| module.path.join(file))
+        .filter(|path| skipped.contains(path))
+        .collect()
+}
+
+/// Replaces the list of files tainer protected, removing it when no files are left.
+pub fn set_protected_files(module: &GitModule, paths: &[PathBuf]) -> bool {
+    let Some(list_path) = protected_list_path(module) else {
+        return false;
+    };
+    let files = paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(&module.path).ok())
+        .map(|file| file.to_string_lossy().replace('\\', "/"))
+        .collect::<Vec<_>>();
+    write_protected_list(&list_path, &files)
+}
+
+const PROTECTED_LIST: &str = "info/tainer-protected";
+
+fn protected_list_path(module: &GitModule) -> Option<PathBuf> {
+    git_output(&module.path, &["rev-parse", "--git-path", PROTECTED_LIST])
+        .map(|path| module.path.join(path))
+}
+
+fn read_protected_list(list_path: &Path) -> Vec<String> {
+    std::fs::read_to_string(list_path)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn write_protected_list(list_path: &Path, files: &[String]) -> bool {
+    if files.is_empty() {
+        return std::fs::remove_file(list_path).is_ok() || !list_path.exists();
+    }
+    let content = files
+        .iter()
+        .map(|file| format!("{}\n", file))
+        .collect::<String>();
+    list_path
+        .parent()
+        .map(|dir| std::fs::create_dir_all(dir).is_ok())
+        .unwrap_or(true)
+        && std::fs::write(list_path, content).is_ok()
+}
+
+/// Runs git in a directory and returns its trimmed stdout, or `None` when it fails or prints
+/// nothing.
+fn git_output(dir: &Path, args: &[&str]) -> Option<String> {
+    std::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|output| !output.is_empty())
+}
+
+fn git_for_file(file_path: &Path, args: &[&str]) -> bool {
+    let (Some(dir), Some(file_name)) = (file_path.parent(), file_path.file_name()) else {
+        return false;
+    };
+
+    std::process::Command::new("git")
+        .args(args)
+        .arg(file_name)
+        .current_dir(dir)
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Returns the lines added in the staged diff, paired with the file they were added to.
+pub fn staged_additions(module: &GitModule) -> Vec<(String, String)> {
+    let output = std::process::Command::new("git")
+        .args([
+            "diff",
+            "--cached",
+            "--unified=0",
+            "--no-color",
+            "--ignore-submodules",
+        ])
+        .current_dir(&module.path)
+        .output()
+        .expect("Failed to run git command");
+
+    let mut file = String::new();
+    let mut additions = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            file = path.strip_prefix("b/").unwrap_or(path).to_string();
+        } else if let Some(added) = line.strip_prefix('+') {
+            additions.push((file.clone(), added.to_string()));
+        }
+    }
+
+    additions
+}
@@ -1,9 +1,24 @@
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
+use db::{Connection, ConnectionArgs};
+use output::Format;
 
+pub(crate) mod browser;
 pub(crate) mod commands;
+pub(crate) mod config;
+pub(crate) mod db;
+pub(crate) mod dirs;
 pub(crate) mod git;
+pub(crate) mod output;
+pub(crate) mod platform;
+pub(crate) mod redact;
+pub(crate) mod schema;
+pub(crate) mod shell;
+pub(crate) mod snapshots;
+pub(crate) mod store;
+pub(crate) mod timezones;
+pub(crate) mod vault;
 pub(crate) mod win;
 
 #[derive(Parser)]
@@ -12,6 +27,9 @@ pub(crate) mod win;
 struct Cli {
     #[clap(short, long)]
     path: Option<PathBuf>,
+    /// Print passwords, user ids and client secrets instead of masking them
+    #[clap(long, global = true)]
+    show_secrets: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -19,7 +37,13 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Sets your connection strings everywhere it needs to be set
-    ConnectionStrings { main: String, service_bus: String },
+    ConnectionStrings {
+        main: String,
+        service_bus: String,
+        /// Keep git from picking up the patched config files (see `unprotect`)
+        #[clap(long)]
+        protect: bool,
+    },
     /// Run a command against each git repository
     Git { command: Vec<String> },
     /// Allow authentication in applicationhost.config
@@ -30,27 +54,363 @@ enum Commands {
     CreateUser {
         name: String,
         email: String,
-        connection_string: String,
+        /// Connection string of the database, the same as --connection-string
+        #[clap(value_name = "CONNECTION_STRING", conflicts_with_all = ["connection_string", "profile"])]
+        positional_connection_string: Option<String>,
+        #[command(flatten)]
+        db: ConnectionArgs,
+        /// Role to give the user, by name (repeatable, defaults to ALL FEATURES)
+        #[clap(short, long = "role")]
+        roles: Vec<String>,
+        #[command(flatten)]
+        options: commands::create_user::UserOptions,
     },
     /// For the inital setup of ELOS, tries to get it into a working state
     Setup {
+        /// Connection string of the main database, the same as --connection-string
+        #[clap(value_name = "MAIN", conflicts_with_all = ["connection_string", "profile"])]
         main: Option<String>,
+        /// Connection string of the service bus database, the same as --service-bus
+        #[clap(value_name = "SERVICE_BUS", conflicts_with = "service_bus")]
+        positional_service_bus: Option<String>,
+        /// Connection string of the service bus database (EnvirotainerNServiceBus)
+        #[clap(long)]
         service_bus: Option<String>,
+        /// Keep git from picking up the patched config files (see `unprotect`)
+        #[clap(long)]
+        protect: bool,
+        /// Scripts to run against the main database if setup has to create it
+        #[clap(long)]
+        bootstrap: Option<PathBuf>,
+        /// Scripts to run against the service bus database if setup has to create it
+        #[clap(long)]
+        service_bus_bootstrap: Option<PathBuf>,
+        #[command(flatten)]
+        db: ConnectionArgs,
     },
     /// Watch files
     Watch,
+    /// Stash or restore changes in every git repository at once
+    Stash {
+        #[command(subcommand)]
+        command: StashCommands,
+    },
+    /// Check out the whole tree, including submodules, in a separate directory
+    Worktree {
+        #[command(subcommand)]
+        command: WorktreeCommands,
+    },
+    /// Distribute shared git hooks to every git repository
+    Hooks {
+        #[command(subcommand)]
+        command: HooksCommands,
+    },
+    /// Let git track changes to the config files protected by `--protect` again
+    Unprotect,
+    /// Check the repositories for common problems
+    Doctor,
+    /// Manage secrets in the encrypted vault, used as ${secret:<name>} in connection strings
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommands,
+    },
+    /// Manage the users in the database
+    User {
+        #[command(subcommand)]
+        command: UserCommands,
+    },
+    /// Work with the database directly
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// List the roles that can be given to users
+    Roles {
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Manage the private MSMQ queues on this machine
+    Queues {
+        #[command(subcommand)]
+        command: QueuesCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum StashCommands {
+    /// Stash every dirty repository under a shared label
+    Push {
+        #[clap(short, long)]
+        message: String,
+        /// Also stash untracked files
+        #[clap(short = 'u', long)]
+        include_untracked: bool,
+    },
+    /// Restore the stashes with the given label
+    Pop { label: String },
+}
+
+#[derive(Subcommand)]
+enum WorktreeCommands {
+    /// Create worktrees of the superproject and every submodule at the given branch
+    Add {
+        dir: PathBuf,
+        branch: String,
+        /// Main connection string to set in the new tree
+        #[clap(long, requires = "service_bus")]
+        main: Option<String>,
+        /// Service bus connection string to set in the new tree
+        #[clap(long, requires = "main")]
+        service_bus: Option<String>,
+        /// Copy and patch applicationhost.config in the new tree
+        #[clap(long)]
+        application_host: bool,
+    },
+    /// Remove the worktrees created by `worktree add`
+    Remove {
+        dir: PathBuf,
+        /// Remove the worktrees even if they have changes
+        #[clap(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HooksCommands {
+    /// Install the hooks into every repository
+    Install {
+        /// Directory in the superproject containing the hook scripts
+        #[clap(long, default_value = commands::hooks::DEFAULT_HOOKS_DIR)]
+        from: PathBuf,
+        /// Symlink the hooks instead of copying them
+        #[clap(short, long)]
+        symlink: bool,
+        /// Only show what would be installed and overwritten
+        #[clap(long)]
+        dry_run: bool,
+        /// Overwrite existing hooks without asking
+        #[clap(short, long)]
+        yes: bool,
+    },
+    /// Remove the installed hooks from every repository
+    Uninstall {
+        /// Directory in the superproject containing the hook scripts
+        #[clap(long, default_value = commands::hooks::DEFAULT_HOOKS_DIR)]
+        from: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueuesCommands {
+    /// List the private queues and the number of messages in them
+    List {
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Create the queues in the manifest that don't exist yet
+    Create {
+        /// Queue manifest, relative to the superproject
+        #[clap(long, default_value = commands::queues::DEFAULT_MANIFEST)]
+        from: PathBuf,
+    },
+    /// Remove every message from a queue
+    Purge {
+        queue: String,
+        /// Purge without asking
+        #[clap(short, long)]
+        yes: bool,
+    },
+    /// Delete a queue and its messages
+    Delete {
+        queue: String,
+        /// Delete without asking
+        #[clap(short, long)]
+        yes: bool,
+    },
+    /// Manage who can use the queues
+    Permissions {
+        #[command(subcommand)]
+        command: QueuePermissionsCommands,
+    },
+    /// Show how the queues differ from the manifest, exits with 1 if they do
+    Diff {
+        /// Queue manifest, relative to the superproject
+        #[clap(long, default_value = commands::queues::DEFAULT_MANIFEST)]
+        from: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueuePermissionsCommands {
+    /// Give an account full control of every queue in the manifest
+    Grant {
+        account: String,
+        /// Queue manifest, relative to the superproject
+        #[clap(long, default_value = commands::queues::DEFAULT_MANIFEST)]
+        from: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretCommands {
+    /// Add or update a secret, prompts for the value without echoing it
+    Set {
+        name: String,
+        /// Read the value from stdin instead, e.g. when piping it from a password manager
+        #[clap(long)]
+        stdin: bool,
+    },
+    /// Print the value of a secret
+    Get { name: String },
+    /// List the names of all secrets
+    List,
+    /// Remove a secret
+    Rm { name: String },
+}
+
+#[derive(Subcommand)]
+enum UserCommands {
+    /// List all users and their roles
+    List {
+        /// Only list users whose email contains this, `*` matches any characters
+        #[clap(long)]
+        email_like: Option<String>,
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Show all details of a user
+    Show {
+        email: String,
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Activate a user
+    Activate {
+        email: String,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Deactivate a user
+    Deactivate {
+        email: String,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Delete a user and their roles
+    Delete {
+        email: String,
+        /// Delete without asking
+        #[clap(short, long)]
+        yes: bool,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Replace the roles of a user with the given role, by name or id
+    SetRole {
+        email: String,
+        role: String,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Create users from a CSV or JSON file, all or nothing
+    Import {
+        file: PathBuf,
+        /// Update users that already exist instead of skipping them
+        #[clap(long)]
+        update: bool,
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// Test the connection one stage at a time and explain what fails
+    Ping {
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Run a query and print the result sets
+    Query {
+        sql: String,
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Start an interactive SQL console
+    Shell {
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Back up the database into a named snapshot
+    Backup {
+        name: String,
+        /// Replace an existing snapshot with the same name
+        #[clap(short, long)]
+        force: bool,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// Replace the database with a snapshot, or with a backup file on the server
+    Restore {
+        snapshot_or_file: String,
+        /// Restore without asking
+        #[clap(short, long)]
+        yes: bool,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
+    /// List the snapshots taken with `db backup`
+    Snapshots {
+        #[clap(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Run the .sql files in a directory in order, skipping the ones already applied
+    Seed {
+        dir: PathBuf,
+        /// Rerun every script, even the ones already applied
+        #[clap(long)]
+        reset: bool,
+        /// Don't wrap the scripts in transactions, a failing script keeps its earlier batches
+        #[clap(long)]
+        no_transaction: bool,
+        #[command(flatten)]
+        db: ConnectionArgs,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    redact::show_secrets(cli.show_secrets);
 
     let root_path = cli
         .path
         .unwrap_or_else(|| std::env::current_dir().expect("Could not get current directory"));
     match &cli.command {
-        Commands::ConnectionStrings { main, service_bus } => {
-            commands::connection_strings::invoke(main, service_bus, &root_path);
+        Commands::ConnectionStrings {
+            main,
+            service_bus,
+            protect,
+        } => {
+            commands::connection_strings::invoke(
+                main,
+                service_bus,
+                *protect,
+                &root_path,
+                platform::native().as_ref(),
+            );
         }
         Commands::Git { command } => {
             commands::git_cmd::invoke(command, &root_path);
@@ -64,15 +424,223 @@ async fn main() {
         Commands::CreateUser {
             name,
             email,
-            connection_string,
+            positional_connection_string,
+            db,
+            roles,
+            options,
         } => {
-            commands::create_user::invoke(name, email, connection_string).await;
+            let db = db.with_connection_string(positional_connection_string);
+            if let Err(e) = commands::create_user::invoke(
+                name,
+                email,
+                options,
+                roles,
+                &connection(&db),
+                db.profile_name(),
+            )
+            .await
+            {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         }
-        Commands::Setup { main, service_bus } => {
-            commands::setup::invoke(main, service_bus, &root_path).await;
+        Commands::Setup {
+            main,
+            positional_service_bus,
+            service_bus,
+            protect,
+            bootstrap,
+            service_bus_bootstrap,
+            db,
+        } => {
+            let bootstrap = commands::setup::Bootstrap {
+                main: bootstrap.clone(),
+                service_bus: service_bus_bootstrap.clone(),
+            };
+            commands::setup::invoke(
+                &db.with_connection_string(main),
+                &service_bus
+                    .clone()
+                    .or_else(|| positional_service_bus.clone()),
+                *protect,
+                &bootstrap,
+                &root_path,
+                platform::native().as_ref(),
+            )
+            .await;
         }
         Commands::Watch => {
             let _ = commands::watch::invoke(&root_path);
         }
+        Commands::Stash { command } => match command {
+            StashCommands::Push {
+                message,
+                include_untracked,
+            } => {
+                commands::stash::push(message, *include_untracked, &root_path);
+            }
+            StashCommands::Pop { label } => {
+                commands::stash::pop(label, &root_path);
+            }
+        },
+        Commands::Worktree { command } => match command {
+            WorktreeCommands::Add {
+                dir,
+                branch,
+                main,
+                service_bus,
+                application_host,
+            } => {
+                let reapply = commands::worktree::Reapply {
+                    connection_strings: main.clone().zip(service_bus.clone()),
+                    application_host: *application_host,
+                };
+                commands::worktree::add(dir, branch, &reapply, &root_path);
+            }
+            WorktreeCommands::Remove { dir, force } => {
+                commands::worktree::remove(dir, *force, &root_path);
+            }
+        },
+        Commands::Hooks { command } => match command {
+            HooksCommands::Install {
+                from,
+                symlink,
+                dry_run,
+                yes,
+            } => {
+                commands::hooks::install(from, *symlink, *dry_run, *yes, &root_path);
+            }
+            HooksCommands::Uninstall { from } => {
+                commands::hooks::uninstall(from, &root_path);
+            }
+        },
+        Commands::Unprotect => {
+            commands::unprotect::invoke(&root_path);
+        }
+        Commands::Doctor => {
+            commands::doctor::invoke(&root_path, platform::native().as_ref());
+        }
+        Commands::Secret { command } => match command {
+            SecretCommands::Set { name, stdin } => {
+                commands::secret::set(name, *stdin);
+            }
+            SecretCommands::Get { name } => {
+                commands::secret::get(name);
+            }
+            SecretCommands::List => {
+                commands::secret::list();
+            }
+            SecretCommands::Rm { name } => {
+                commands::secret::remove(name);
+            }
+        },
+        Commands::User { command } => match command {
+            UserCommands::List {
+                email_like,
+                format,
+                db,
+            } => {
+                commands::user::list(email_like, *format, &connection(db)).await;
+            }
+            UserCommands::Show { email, format, db } => {
+                commands::user::show(email, *format, &connection(db)).await;
+            }
+            UserCommands::Activate { email, db } => {
+                commands::user::set_active(email, true, &connection(db)).await;
+            }
+            UserCommands::Deactivate { email, db } => {
+                commands::user::set_active(email, false, &connection(db)).await;
+            }
+            UserCommands::Delete { email, yes, db } => {
+                commands::user::delete(email, *yes, &connection(db)).await;
+            }
+            UserCommands::SetRole { email, role, db } => {
+                commands::user::set_role(email, role, &connection(db)).await;
+            }
+            UserCommands::Import {
+                file,
+                update,
+                format,
+                db,
+            } => {
+                commands::user_import::invoke(
+                    file,
+                    *update,
+                    *format,
+                    &connection(db),
+                    db.profile_name(),
+                )
+                .await;
+            }
+        },
+        Commands::Db { command } => match command {
+            DbCommands::Ping { db } => {
+                commands::db_ping::invoke(&connection(db)).await;
+            }
+            DbCommands::Query { sql, format, db } => {
+                commands::db_query::query(sql, *format, &connection(db)).await;
+            }
+            DbCommands::Shell { format, db } => {
+                commands::db_query::shell(*format, &connection(db)).await;
+            }
+            DbCommands::Backup { name, force, db } => {
+                commands::db_backup::backup(name, *force, &connection(db)).await;
+            }
+            DbCommands::Restore {
+                snapshot_or_file,
+                yes,
+                db,
+            } => {
+                commands::db_backup::restore(snapshot_or_file, *yes, &connection(db)).await;
+            }
+            DbCommands::Snapshots { format } => {
+                commands::db_backup::list(*format);
+            }
+            DbCommands::Seed {
+                dir,
+                reset,
+                no_transaction,
+                db,
+            } => {
+                commands::db_seed::invoke(dir, *reset, !*no_transaction, &connection(db)).await;
+            }
+        },
+        Commands::Roles { format, db } => {
+            commands::roles::invoke(*format, &connection(db)).await;
+        }
+        Commands::Queues { command } => {
+            let platform = platform::native();
+            let platform = platform.as_ref();
+            match command {
+                QueuesCommands::List { format } => {
+                    commands::queues::list(platform, *format);
+                }
+                QueuesCommands::Create { from } => {
+                    commands::queues::create(platform, &root_path.join(from));
+                }
+                QueuesCommands::Purge { queue, yes } => {
+                    commands::queues::purge(platform, queue, *yes);
+                }
+                QueuesCommands::Delete { queue, yes } => {
+                    commands::queues::delete(platform, queue, *yes);
+                }
+                QueuesCommands::Permissions { command } => match command {
+                    QueuePermissionsCommands::Grant { account, from } => {
+                        commands::queues::grant(platform, account, &root_path.join(from));
+                    }
+                },
+                QueuesCommands::Diff { from } => {
+                    commands::queues::diff(platform, &root_path.join(from));
+                }
+            }
+        }
     }
 }
+
+/// The connection string, login and TLS options to connect with.
+fn connection(db: &ConnectionArgs) -> Connection {
+    db.connection().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
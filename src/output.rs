@@ -0,0 +1,128 @@
+use clap::ValueEnum;
+use console::style;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+/// Prints rows as an aligned table, as CSV or as a JSON array of objects keyed by column.
+pub fn print_rows(columns: &[&str], rows: &[Vec<Value>], format: Format) {
+    match format {
+        Format::Table => print_table(columns, rows),
+        Format::Csv => print_csv(columns, rows),
+        Format::Json => {
+            let objects = rows
+                .iter()
+                .map(|row| to_object(columns, row))
+                .collect::<Vec<_>>();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&objects).expect("Rows are valid JSON")
+            );
+        }
+    }
+}
+
+/// Prints a single record, one column per line in table format.
+pub fn print_record(columns: &[&str], row: &[Value], format: Format) {
+    match format {
+        Format::Table => {
+            let width = columns.iter().map(|c| c.len()).max().unwrap_or(0);
+            for (column, value) in columns.iter().zip(row) {
+                println!("{:<width$}  {}", style(column).bold(), cell(value));
+            }
+        }
+        Format::Csv => print_csv(columns, &[row.to_vec()]),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&to_object(columns, row)).expect("Row is valid JSON")
+        ),
+    }
+}
+
+fn print_table(columns: &[&str], rows: &[Vec<Value>]) {
+    let cells = rows
+        .iter()
+        .map(|row| row.iter().map(cell).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let widths = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(column.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+
+    let header = columns
+        .iter()
+        .zip(&widths)
+        .map(|(column, width)| format!("{:<width$}", column, width = width))
+        .collect::<Vec<_>>()
+        .join("  ");
+    println!("{}", style(header.trim_end()).bold());
+    for row in cells {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+fn print_csv(columns: &[&str], rows: &[Vec<Value>]) {
+    let header = columns
+        .iter()
+        .map(|column| csv_field(column))
+        .collect::<Vec<_>>()
+        .join(",");
+    println!("{}", header);
+    for row in rows {
+        let line = row
+            .iter()
+            .map(|value| match value {
+                Value::Null => String::new(),
+                value => csv_field(&cell(value)),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        println!("{}", line);
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values.iter().map(cell).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+fn to_object(columns: &[&str], row: &[Value]) -> Value {
+    Value::Object(
+        columns
+            .iter()
+            .zip(row)
+            .map(|(column, value)| (column.to_string(), value.clone()))
+            .collect::<Map<_, _>>(),
+    )
+}
@@ -0,0 +1,292 @@
+use std::{path::Path, process::Command};
+
+use crate::win::Windows;
+
+/// What setup, the config and the queue commands need from the machine they run on, so they
+/// also work on Linux and in WSL, or fail there with a clear error.
+pub(crate) trait Platform {
+    /// MSMQ is Windows only, elsewhere the queue steps are skipped.
+    fn has_msmq(&self) -> bool;
+
+    fn msmq_enabled(&self) -> Result<bool, String>;
+
+    fn enable_msmq(&self) -> Result<(), String>;
+
+    fn execute_ps1(&self, script_path: &Path, args: &[&str]) -> Result<(), String>;
+
+    /// The name the config files key the machine specific connection strings by.
+    fn computer_name(&self) -> String;
+
+    fn account_name(&self) -> String;
+
+    /// The private queues on this machine.
+    fn queues(&self) -> Result<Vec<Queue>, String>;
+
+    fn create_queue(&self, name: &str, transactional: bool) -> Result<(), String>;
+
+    /// Removes every message from the queue.
+    fn purge_queue(&self, name: &str) -> Result<(), String>;
+
+    fn delete_queue(&self, name: &str) -> Result<(), String>;
+
+    /// Gives `account` full control of the queue.
+    fn grant_queue_access(&self, name: &str, account: &str) -> Result<(), String>;
+}
+
+/// A private MSMQ queue, by its name without the `private$\` prefix.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Queue {
+    pub name: String,
+    pub transactional: bool,
+    pub messages: u64,
+}
+
+/// The platform tainer is running on.
+pub(crate) fn native() -> Box<dyn Platform> {
+    if cfg!(windows) {
+        Box::new(Windows::new())
+    } else {
+        Box::new(Portable)
+    }
+}
+
+/// Anything that isn't Windows, with the names from the environment and scripts run by
+/// PowerShell 7 (`pwsh`) if it's installed.
+pub(crate) struct Portable;
+
+impl Platform for Portable {
+    fn has_msmq(&self) -> bool {
+        false
+    }
+
+    fn msmq_enabled(&self) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    fn enable_msmq(&self) -> Result<(), String> {
+        Err(NO_MSMQ.to_string())
+    }
+
+    fn execute_ps1(&self, script_path: &Path, args: &[&str]) -> Result<(), String> {
+        let status = Command::new("pwsh")
+            .args(["-NoProfile", "-File"])
+            .arg(script_path)
+            .args(args)
+            .status()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    "PowerShell (pwsh) is not installed, see https://aka.ms/powershell".to_string()
+                }
+                _ => format!("Failed to execute PowerShell: {}", e),
+            })?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err("Failed to execute PowerShell script.".to_string())
+        }
+    }
+
+    /// The host name without its domain, like `COMPUTERNAME` on Windows. In WSL this is the
+    /// name of the Windows machine.
+    fn computer_name(&self) -> String {
+        env_var(&["COMPUTERNAME", "HOSTNAME"])
+            .or_else(|| {
+                std::fs::read_to_string("/etc/hostname")
+                    .ok()
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+            })
+            .map(|name| name.split('.').next().unwrap_or_default().to_string())
+            .unwrap_or_else(|| "localhost".to_string())
+    }
+
+    fn account_name(&self) -> String {
+        env_var(&["USERNAME", "USER", "LOGNAME"]).unwrap_or_default()
+    }
+
+    fn queues(&self) -> Result<Vec<Queue>, String> {
+        Err(NO_MSMQ.to_string())
+    }
+
+    fn create_queue(&self, _name: &str, _transactional: bool) -> Result<(), String> {
+        Err(NO_MSMQ.to_string())
+    }
+
+    fn purge_queue(&self, _name: &str) -> Result<(), String> {
+        Err(NO_MSMQ.to_string())
+    }
+
+    fn delete_queue(&self, _name: &str) -> Result<(), String> {
+        Err(NO_MSMQ.to_string())
+    }
+
+    fn grant_queue_access(&self, _name: &str, _account: &str) -> Result<(), String> {
+        Err(NO_MSMQ.to_string())
+    }
+}
+
+static NO_MSMQ: &str = "MSMQ is only available on Windows";
+
+pub(crate) fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+#[cfg(test)]
+pub(crate) use mock::MockPlatform;
+
+#[cfg(test)]
+mod mock {
+    use std::{
+        cell::{Cell, RefCell},
+        path::Path,
+    };
+
+    use super::{Platform, Queue};
+
+    /// Answers with the scripted values and records what was asked of it.
+    pub(crate) struct MockPlatform {
+        pub computer_name: String,
+        pub account_name: String,
+        /// `None` for a platform without MSMQ
+        pub msmq: Option<Cell<bool>>,
+        pub enable_error: Option<String>,
+        pub script_error: Option<String>,
+        pub queues: RefCell<Vec<Queue>>,
+        pub calls: RefCell<Vec<String>>,
+    }
+
+    impl Default for MockPlatform {
+        fn default() -> Self {
+            MockPlatform {
+                computer_name: "PINKGOLD".to_string(),
+                account_name: "pontus".to_string(),
+                msmq: Some(Cell::new(true)),
+                enable_error: None,
+                script_error: None,
+                queues: RefCell::new(Vec::new()),
+                calls: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl MockPlatform {
+        /// A platform with MSMQ and these queues, all of them transactional and empty.
+        pub(crate) fn with_queues(names: &[&str]) -> MockPlatform {
+            let queues = names
+                .iter()
+                .map(|name| Queue {
+                    name: name.to_string(),
+                    transactional: true,
+                    messages: 0,
+                })
+                .collect();
+            MockPlatform {
+                queues: RefCell::new(queues),
+                ..Default::default()
+            }
+        }
+
+        pub(crate) fn queue_names(&self) -> Vec<String> {
+            self.queues
+                .borrow()
+                .iter()
+                .map(|queue| queue.name.clone())
+                .collect()
+        }
+
+        fn record(&self, call: String) {
+            self.calls.borrow_mut().push(call);
+        }
+
+        fn find_queue(&self, name: &str) -> Result<usize, String> {
+            self.queues
+                .borrow()
+                .iter()
+                .position(|queue| queue.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("No queue named {}", name))
+        }
+    }
+
+    impl Platform for MockPlatform {
+        fn has_msmq(&self) -> bool {
+            self.msmq.is_some()
+        }
+
+        fn msmq_enabled(&self) -> Result<bool, String> {
+            self.record("msmq_enabled".to_string());
+            Ok(self.msmq.as_ref().is_some_and(Cell::get))
+        }
+
+        fn enable_msmq(&self) -> Result<(), String> {
+            self.record("enable_msmq".to_string());
+            if let Some(e) = &self.enable_error {
+                return Err(e.clone());
+            }
+            match &self.msmq {
+                Some(enabled) => {
+                    enabled.set(true);
+                    Ok(())
+                }
+                None => Err("MSMQ is only available on Windows".to_string()),
+            }
+        }
+
+        fn execute_ps1(&self, script_path: &Path, args: &[&str]) -> Result<(), String> {
+            let file_name = script_path.file_name().unwrap_or_default();
+            self.record(format!(
+                "{} {}",
+                file_name.to_string_lossy(),
+                args.join(" ")
+            ));
+            self.script_error.clone().map_or(Ok(()), Err)
+        }
+
+        fn computer_name(&self) -> String {
+            self.computer_name.clone()
+        }
+
+        fn account_name(&self) -> String {
+            self.account_name.clone()
+        }
+
+        fn queues(&self) -> Result<Vec<Queue>, String> {
+            Ok(self.queues.borrow().clone())
+        }
+
+        fn create_queue(&self, name: &str, transactional: bool) -> Result<(), String> {
+            self.record(format!("create_queue {}", name));
+            if self.find_queue(name).is_ok() {
+                return Err(format!("Queue {} already exists", name));
+            }
+            self.queues.borrow_mut().push(Queue {
+                name: name.to_string(),
+                transactional,
+                messages: 0,
+            });
+            Ok(())
+        }
+
+        fn purge_queue(&self, name: &str) -> Result<(), String> {
+            self.record(format!("purge_queue {}", name));
+            let index = self.find_queue(name)?;
+            self.queues.borrow_mut()[index].messages = 0;
+            Ok(())
+        }
+
+        fn delete_queue(&self, name: &str) -> Result<(), String> {
+            self.record(format!("delete_queue {}", name));
+            let index = self.find_queue(name)?;
+            self.queues.borrow_mut().remove(index);
+            Ok(())
+        }
+
+        fn grant_queue_access(&self, name: &str, account: &str) -> Result<(), String> {
+            self.record(format!("grant_queue_access {} {}", name, account));
+            self.find_queue(name).map(|_| ())
+        }
+    }
+}
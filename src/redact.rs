@@ -0,0 +1,198 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHOW_SECRETS: AtomicBool = AtomicBool::new(false);
+
+static MASK: &str = "*****";
+
+/// Connection string keywords whose values are masked.
+static SECRET_KEYS: [&str; 6] = ["password", "pwd", "user id", "userid", "uid", "user"];
+
+/// JSON properties whose values are masked.
+static SECRET_PROPERTIES: [&str; 2] = ["clientsecret", "password"];
+
+/// Turns off redaction for the rest of the process, used by `--show-secrets`.
+pub fn show_secrets(show: bool) {
+    SHOW_SECRETS.store(show, Ordering::Relaxed);
+}
+
+/// Masks passwords, user ids and client secrets in connection strings and JSON so the text
+/// can be printed. Everything that may contain a connection string should go through here
+/// before it is printed.
+pub fn redact(text: &str) -> String {
+    if SHOW_SECRETS.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+
+    redact_json_properties(&redact_connection_string_keys(text))
+}
+
+fn redact_connection_string_keys(text: &str) -> String {
+    // the keys are ASCII, and ASCII lowercasing keeps every byte offset of the text
+    let lower = text.to_ascii_lowercase();
+
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < text.len() {
+        if is_key_start(text, i) {
+            if let Some(value_start) = SECRET_KEYS
+                .iter()
+                .find_map(|key| value_start_after_key(&lower, i, key))
+            {
+                let value_end = connection_string_value_end(text, value_start);
+                result.push_str(&text[i..value_start]);
+                if value_end > value_start {
+                    result.push_str(MASK);
+                }
+                i = value_end;
+                continue;
+            }
+        }
+
+        let c = text[i..]
+            .chars()
+            .next()
+            .expect("Index is within the string");
+        result.push(c);
+        i += c.len_utf8();
+    }
+
+    result
+}
+
+fn redact_json_properties(text: &str) -> String {
+    let lower = text.to_ascii_lowercase();
+
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while let Some(offset) = lower[i..].find('"') {
+        let quote = i + offset;
+        let value = SECRET_PROPERTIES.iter().find_map(|property| {
+            let rest = lower[quote + 1..]
+                .strip_prefix(property)?
+                .strip_prefix('"')?;
+            let rest_trimmed = rest.trim_start().strip_prefix(':')?.trim_start();
+            rest_trimmed.strip_prefix('"')?;
+            Some(lower.len() - rest_trimmed.len() + 1)
+        });
+
+        match value {
+            Some(value_start) => {
+                let value_end = json_string_end(text, value_start);
+                result.push_str(&text[i..value_start]);
+                if value_end > value_start {
+                    result.push_str(MASK);
+                }
+                i = value_end;
+            }
+            None => {
+                result.push_str(&text[i..=quote]);
+                i = quote + 1;
+            }
+        }
+    }
+    result.push_str(&text[i..]);
+
+    result
+}
+
+/// Keys start at the beginning of the text or after a separator, so `Data Source=...` and
+/// `NotAPassword=` aren't mistaken for keys.
+fn is_key_start(text: &str, i: usize) -> bool {
+    text[..i]
+        .chars()
+        .next_back()
+        .map(|c| c == ';' || c == '"' || c == '\'' || c.is_whitespace())
+        .unwrap_or(true)
+}
+
+fn value_start_after_key(lower: &str, i: usize, key: &str) -> Option<usize> {
+    let rest = lower[i..].strip_prefix(key)?.trim_start_matches(' ');
+    let value = rest.strip_prefix('=')?.trim_start_matches(' ');
+    Some(lower.len() - value.len())
+}
+
+fn connection_string_value_end(text: &str, start: usize) -> usize {
+    let value = &text[start..];
+    // quoted values may contain semicolons
+    if let Some(quote) = value.chars().next().filter(|c| *c == '\'' || *c == '"') {
+        return value[1..]
+            .find(quote)
+            .map(|end| start + end + 2)
+            .unwrap_or(text.len());
+    }
+
+    value
+        .find([';', '"', '\'', '\n', '\r'])
+        .map(|end| start + end)
+        .unwrap_or(text.len())
+}
+
+fn json_string_end(text: &str, start: usize) -> usize {
+    let mut escaped = false;
+    for (offset, c) in text[start..].char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return start + offset,
+            _ => escaped = false,
+        }
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(text: &str) -> String {
+        redact_json_properties(&redact_connection_string_keys(text))
+    }
+
+    #[test]
+    fn masks_connection_string_keys() {
+        assert_eq!(
+            redact("Data Source=PINKGOLD;User Id=sa;Password=hunter2;Initial Catalog=Elos"),
+            "Data Source=PINKGOLD;User Id=*****;Password=*****;Initial Catalog=Elos"
+        );
+        assert_eq!(
+            redact("server=db; UID = sa ;PWD=hunter2"),
+            "server=db; UID = *****;PWD=*****"
+        );
+        assert_eq!(
+            redact("NotAPassword=visible;Password="),
+            "NotAPassword=visible;Password="
+        );
+    }
+
+    #[test]
+    fn masks_quoted_values_with_separators() {
+        assert_eq!(
+            redact("Server=db;Password='a;b=c';Database=Elos"),
+            "Server=db;Password=*****;Database=Elos"
+        );
+        assert_eq!(
+            redact(r#"<add connectionString="Server=db;Pwd=x" />"#),
+            r#"<add connectionString="Server=db;Pwd=*****" />"#
+        );
+    }
+
+    #[test]
+    fn masks_json_properties() {
+        assert_eq!(
+            redact(r#"{"ClientSecret": "s3cr\"et", "ClientId": "app", "password":"x"}"#),
+            r#"{"ClientSecret": "*****", "ClientId": "app", "password":"*****"}"#
+        );
+    }
+
+    #[test]
+    fn masks_text_that_changes_length_when_lowercased() {
+        assert_eq!(
+            redact("Data Source=İstanbul;Password=hunter2"),
+            "Data Source=İstanbul;Password=*****"
+        );
+        assert_eq!(
+            redact(r#"{"Name": "Straẞe", "Password": "hunter2"}"#),
+            r#"{"Name": "Straẞe", "Password": "*****"}"#
+        );
+        assert_eq!(redact("Password=pässwörd;"), "Password=*****;");
+    }
+}
@@ -0,0 +1,193 @@
+use crate::db::SqlClient;
+
+/// A table as a command expects to find it in the `dbo` schema.
+pub struct Table {
+    pub name: &'static str,
+    pub columns: &'static [Column],
+    /// Whether every NOT NULL column of the table has to be in `columns`, for tables the
+    /// command inserts rows into.
+    pub inserted: bool,
+}
+
+pub struct Column {
+    pub name: &'static str,
+    pub kind: Kind,
+    /// Whether the command writes NULL into the column.
+    pub null: bool,
+}
+
+impl Column {
+    pub const fn new(name: &'static str, kind: Kind) -> Column {
+        Column {
+            name,
+            kind,
+            null: false,
+        }
+    }
+
+    pub const fn nullable(name: &'static str, kind: Kind) -> Column {
+        Column {
+            name,
+            kind,
+            null: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Guid,
+    Text,
+    /// Ids passed as text, which SQL Server converts to uniqueidentifier
+    GuidOrText,
+    Bit,
+    DateTime,
+}
+
+impl Kind {
+    fn accepts(self, data_type: &str) -> bool {
+        let text = matches!(data_type, "nvarchar" | "varchar" | "nchar" | "char");
+        match self {
+            Kind::Guid => data_type == "uniqueidentifier",
+            Kind::Text => text,
+            Kind::GuidOrText => text || data_type == "uniqueidentifier",
+            Kind::Bit => data_type == "bit",
+            Kind::DateTime => matches!(
+                data_type,
+                "datetime" | "datetime2" | "smalldatetime" | "datetimeoffset"
+            ),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::Guid => "uniqueidentifier",
+            Kind::Text => "text (nvarchar, varchar, nchar or char)",
+            Kind::GuidOrText => "uniqueidentifier or text",
+            Kind::Bit => "bit",
+            Kind::DateTime => "a date and time type",
+        }
+    }
+}
+
+/// A column as it is in the database, from `INFORMATION_SCHEMA.COLUMNS`.
+struct DatabaseColumn {
+    table: String,
+    name: String,
+    data_type: String,
+    nullable: bool,
+    /// Has a default, or is filled in by SQL Server (identity, computed, rowversion)
+    generated: bool,
+}
+
+/// Reads the `dbo` schema and checks the tables against what the command expects. Fails with
+/// every mismatch, one per line, so they can all be fixed at once.
+pub async fn verify(client: &mut SqlClient, tables: &[Table]) -> Result<(), String> {
+    let columns = read_columns(client)
+        .await
+        .map_err(|e| format!("Could not read the database schema: {}", e))?;
+
+    let problems = tables
+        .iter()
+        .flat_map(|table| check_table(table, &columns))
+        .collect::<Vec<_>>();
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "The database schema doesn't match what this version of tainer expects:\n{}",
+        problems
+            .iter()
+            .map(|problem| format!("  - {}", problem))
+            .collect::<Vec<_>>()
+            .join("\n")
+    ))
+}
+
+async fn read_columns(
+    client: &mut SqlClient,
+) -> Result<Vec<DatabaseColumn>, tiberius::error::Error> {
+    let query = r#"SELECT [TABLE_NAME]
+            ,[COLUMN_NAME]
+            ,[DATA_TYPE]
+            ,[IS_NULLABLE]
+            ,CASE WHEN [COLUMN_DEFAULT] IS NOT NULL
+                OR [DATA_TYPE] = 'timestamp'
+                OR COLUMNPROPERTY(OBJECT_ID(QUOTENAME([TABLE_SCHEMA]) + '.' + QUOTENAME([TABLE_NAME])), [COLUMN_NAME], 'IsIdentity') = 1
+                OR COLUMNPROPERTY(OBJECT_ID(QUOTENAME([TABLE_SCHEMA]) + '.' + QUOTENAME([TABLE_NAME])), [COLUMN_NAME], 'IsComputed') = 1
+                THEN 1 ELSE 0 END
+        FROM INFORMATION_SCHEMA.COLUMNS
+        WHERE [TABLE_SCHEMA] = 'dbo'"#;
+
+    let rows = client.query(query, &[]).await?.into_first_result().await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some(DatabaseColumn {
+                table: row.get::<&str, _>(0)?.to_string(),
+                name: row.get::<&str, _>(1)?.to_string(),
+                data_type: row.get::<&str, _>(2)?.to_lowercase(),
+                nullable: row.get::<&str, _>(3)? == "YES",
+                generated: row.get::<i32, _>(4)? == 1,
+            })
+        })
+        .collect())
+}
+
+fn check_table(table: &Table, columns: &[DatabaseColumn]) -> Vec<String> {
+    let table_columns = columns
+        .iter()
+        .filter(|column| column.table.eq_ignore_ascii_case(table.name))
+        .collect::<Vec<_>>();
+    if table_columns.is_empty() {
+        return vec![format!("Table [dbo].[{}] does not exist", table.name)];
+    }
+
+    let mut problems = Vec::new();
+    for expected in table.columns {
+        let Some(column) = table_columns
+            .iter()
+            .find(|column| column.name.eq_ignore_ascii_case(expected.name))
+        else {
+            problems.push(format!(
+                "[dbo].[{}] has no column [{}]",
+                table.name, expected.name
+            ));
+            continue;
+        };
+
+        if !expected.kind.accepts(&column.data_type) {
+            problems.push(format!(
+                "[dbo].[{}].[{}] is {}, expected {}",
+                table.name,
+                column.name,
+                column.data_type,
+                expected.kind.describe()
+            ));
+        }
+        if expected.null && !column.nullable {
+            problems.push(format!(
+                "[dbo].[{}].[{}] does not allow NULL, which tainer writes into it",
+                table.name, column.name
+            ));
+        }
+    }
+
+    if table.inserted {
+        for column in table_columns {
+            let known = table
+                .columns
+                .iter()
+                .any(|expected| expected.name.eq_ignore_ascii_case(&column.name));
+            if !known && !column.nullable && !column.generated {
+                problems.push(format!(
+                    "[dbo].[{}].[{}] is required (NOT NULL without a default), but tainer doesn't set it",
+                    table.name, column.name
+                ));
+            }
+        }
+    }
+
+    problems
+}
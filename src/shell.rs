@@ -0,0 +1,382 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Marks the end of a command's output, followed by the command's id and True or False.
+static DONE_MARKER: &str = "__tainer_done__";
+
+/// How long a command may run before the session gives up on it, long enough for DISM to
+/// enable MSMQ. A command waiting for input would otherwise hang tainer.
+static COMMAND_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long to wait for the rest of stderr once PowerShell is gone.
+static STDERR_GRACE: Duration = Duration::from_millis(500);
+
+/// Defined once per session. Commands arrive base64 encoded so they can span lines, errors
+/// are merged into the output, and a command succeeded if it raised no errors and the last
+/// native program exited with 0.
+static RUNNER: &str = r#"[Console]::OutputEncoding = [Text.Encoding]::UTF8; function __tainer_run([string]$Command, [string]$Id) { $Error.Clear(); $global:LASTEXITCODE = 0; try { Invoke-Expression ([Text.Encoding]::UTF8.GetString([Convert]::FromBase64String($Command))) 2>&1 | Out-String -Stream -Width 4096 } catch { $_ | Out-String -Stream -Width 4096 }; $ok = ($Error.Count -eq 0) -and ($LASTEXITCODE -eq 0); "__tainer_done__ $Id $ok" }"#;
+
+#[derive(Debug)]
+pub(crate) struct ShellOutput {
+    pub stdout: String,
+    pub success: bool,
+}
+
+/// Something that runs PowerShell commands, so the Windows platform can be tested without
+/// Windows.
+pub(crate) trait Shell {
+    fn run(&mut self, command: &str) -> Result<ShellOutput, String>;
+}
+
+/// One PowerShell process kept alive for all commands, since starting one takes a few
+/// hundred milliseconds. Its output is read on threads, so a command that never finishes can
+/// be given up on.
+pub(crate) struct PowerShellSession {
+    program: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<String>,
+    stderr: Arc<Mutex<String>>,
+    stderr_reader: JoinHandle<()>,
+    timeout: Duration,
+    next_id: u64,
+}
+
+impl PowerShellSession {
+    /// Starts PowerShell 7 (`pwsh`) if it's installed, Windows PowerShell otherwise.
+    pub(crate) fn start() -> Result<PowerShellSession, String> {
+        let args = ["-NoLogo", "-NoProfile", "-NonInteractive", "-Command", "-"];
+        PowerShellSession::spawn("pwsh", &args)
+            .or_else(|_| PowerShellSession::spawn("powershell", &args))
+    }
+
+    /// Starts `program`, which has to read commands from stdin like `powershell -Command -`.
+    pub(crate) fn spawn(program: &str, args: &[&str]) -> Result<PowerShellSession, String> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}", program, e))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+
+        let (lines, stdout) = mpsc::channel();
+        let mut reader = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        thread::spawn(move || loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(read) if read > 0 && lines.send(line).is_ok() => {}
+                _ => break,
+            }
+        });
+
+        let stderr = Arc::new(Mutex::new(String::new()));
+        let mut reader = child.stderr.take().expect("stderr is piped");
+        let buffer = Arc::clone(&stderr);
+        let stderr_reader = thread::spawn(move || {
+            let mut chunk = [0; 1024];
+            while let Ok(read @ 1..) = reader.read(&mut chunk) {
+                let text = String::from_utf8_lossy(&chunk[..read]);
+                buffer.lock().expect("stderr lock").push_str(&text);
+            }
+        });
+
+        let mut session = PowerShellSession {
+            program: program.to_string(),
+            child,
+            stdin,
+            stdout,
+            stderr,
+            stderr_reader,
+            timeout: COMMAND_TIMEOUT,
+            next_id: 0,
+        };
+        session.send(RUNNER)?;
+        Ok(session)
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| self.failure(format!("{} has exited ({})", self.program, e)))
+    }
+
+    /// Stops PowerShell and adds what it wrote to stderr to `message`.
+    fn failure(&mut self, message: String) -> String {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let grace = Instant::now() + STDERR_GRACE;
+        while !self.stderr_reader.is_finished() && Instant::now() < grace {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let stderr = std::mem::take(&mut *self.stderr.lock().expect("stderr lock"));
+        match stderr.trim() {
+            "" => message,
+            stderr => format!("{}:\n{}", message, stderr),
+        }
+    }
+}
+
+impl Shell for PowerShellSession {
+    fn run(&mut self, command: &str) -> Result<ShellOutput, String> {
+        self.next_id += 1;
+        let id = self.next_id.to_string();
+        self.send(&format!(
+            "__tainer_run '{}' '{}'",
+            base64(command.as_bytes()),
+            id
+        ))?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut stdout = String::new();
+        loop {
+            let line = match self
+                .stdout
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(self.failure(format!(
+                        "{} did not finish the command within {} seconds and was stopped, it may be waiting for input",
+                        self.program,
+                        self.timeout.as_secs()
+                    )))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(self.failure(format!(
+                        "{} exited in the middle of a command",
+                        self.program
+                    )))
+                }
+            };
+
+            let mut parts = line.trim_end().splitn(3, ' ');
+            if parts.next() == Some(DONE_MARKER) && parts.next() == Some(id.as_str()) {
+                return Ok(ShellOutput {
+                    stdout,
+                    success: parts
+                        .next()
+                        .is_some_and(|ok| ok.eq_ignore_ascii_case("true")),
+                });
+            }
+            stdout.push_str(line.trim_end_matches(['\r', '\n']));
+            stdout.push('\n');
+        }
+    }
+}
+
+impl Drop for PowerShellSession {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+pub(crate) use fake::FakeShell;
+
+#[cfg(test)]
+mod fake {
+    use super::*;
+
+    /// Answers commands containing a pattern with its scripted output, and records every
+    /// command.
+    #[derive(Default)]
+    pub(crate) struct FakeShell {
+        pub replies: Vec<(&'static str, &'static str)>,
+        pub commands: Vec<String>,
+    }
+
+    impl FakeShell {
+        pub(crate) fn new(replies: &[(&'static str, &'static str)]) -> FakeShell {
+            FakeShell {
+                replies: replies.to_vec(),
+                commands: Vec::new(),
+            }
+        }
+    }
+
+    impl Shell for FakeShell {
+        fn run(&mut self, command: &str) -> Result<ShellOutput, String> {
+            self.commands.push(command.to_string());
+            let stdout = self
+                .replies
+                .iter()
+                .find(|(pattern, _)| command.contains(pattern))
+                .map(|(_, output)| format!("{}\n", output))
+                .ok_or_else(|| format!("No reply scripted for {}", command))?;
+            Ok(ShellOutput {
+                stdout,
+                success: true,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for PowerShell with `sh`: ignores the runner definition, decodes each command
+    /// and echoes it back with a `FAIL` command failing, then prints the done marker.
+    #[cfg(unix)]
+    static SH_SHELL: &str = r#"
+        while read -r name command id; do
+            [ "$name" = "__tainer_run" ] || continue
+            decoded=$(printf '%s' "$command" | tr -d "'" | base64 -d)
+            id=$(printf '%s' "$id" | tr -d "'")
+            printf '%s\n' "$decoded"
+            case "$decoded" in FAIL*) ok=False ;; *) ok=True ;; esac
+            echo "__tainer_done__ $id $ok"
+        done
+    "#;
+
+    #[cfg(unix)]
+    fn sh_session() -> PowerShellSession {
+        PowerShellSession::spawn("sh", &["-c", SH_SHELL]).unwrap()
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(
+            base64("Write-Output $env:COMPUTERNAME".as_bytes()),
+            "V3JpdGUtT3V0cHV0ICRlbnY6Q09NUFVURVJOQU1F"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn session_frames_the_output_of_each_command() {
+        let mut session = sh_session();
+
+        let first = session.run("Write-Output one").unwrap();
+        let second = session.run("line one\nline two").unwrap();
+
+        assert_eq!(first.stdout, "Write-Output one\n");
+        assert!(first.success);
+        assert_eq!(second.stdout, "line one\nline two\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn session_reports_failed_commands() {
+        let mut session = sh_session();
+
+        let failed = session.run("FAIL").unwrap();
+        let next = session.run("Write-Output ok").unwrap();
+
+        assert!(!failed.success);
+        assert!(next.success);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn session_reports_a_shell_that_exited() {
+        let mut session = PowerShellSession::spawn("sh", &["-c", "read -r line"]).unwrap();
+
+        assert!(session.run("Write-Output one").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn session_errors_include_stderr() {
+        let script = "read -r runner; echo 'ParserError: the runner is broken' >&2; read -r line";
+        let mut session = PowerShellSession::spawn("sh", &["-c", script]).unwrap();
+
+        let error = session.run("Write-Output one").unwrap_err();
+
+        assert!(
+            error.contains("exited in the middle of a command"),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("ParserError: the runner is broken"),
+            "{}",
+            error
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn session_stops_a_command_that_never_finishes() {
+        let script = "while read -r line; do echo 'Enter a value:' >&2; done";
+        let mut session = PowerShellSession::spawn("sh", &["-c", script]).unwrap();
+        session.timeout = Duration::from_millis(200);
+
+        let start = Instant::now();
+        let error = session.run("Read-Host").unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(error.contains("did not finish the command"), "{}", error);
+        assert!(error.contains("Enter a value:"), "{}", error);
+        assert!(session.run("Write-Output one").is_err());
+    }
+
+    /// `cargo test --release -- --ignored --nocapture bench` compares one session against a
+    /// process per command. Needs PowerShell, the `sh` stand-in is too slow to say anything.
+    #[test]
+    #[ignore]
+    fn bench_session_against_process_per_command() {
+        const COMMANDS: u32 = 20;
+
+        let Ok(mut session) = PowerShellSession::start() else {
+            println!("PowerShell is not installed, skipping the benchmark");
+            return;
+        };
+
+        let start = Instant::now();
+        for _ in 0..COMMANDS {
+            Command::new(&session.program)
+                .args(["-NoProfile", "-Command", "Write-Output $env:USERNAME"])
+                .output()
+                .unwrap();
+        }
+        let per_process = start.elapsed() / COMMANDS;
+
+        let start = Instant::now();
+        for _ in 0..COMMANDS {
+            session.run("Write-Output $env:USERNAME").unwrap();
+        }
+        let per_session_command = start.elapsed() / COMMANDS;
+
+        println!(
+            "{}: {:?} per process, {:?} per command in a session",
+            session.program, per_process, per_session_command
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dirs;
+
+static CATALOG_FILE: &str = "snapshots.json";
+
+/// Named database backups taken with `db backup`, so they can be restored by name.
+pub struct Catalog {
+    path: PathBuf,
+    snapshots: Vec<Snapshot>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub name: String,
+    pub server: String,
+    pub database: String,
+    /// Path of the backup file on the database server
+    pub file: String,
+    pub created: String,
+}
+
+impl Snapshot {
+    /// Server and database names are case-insensitive in SQL Server, snapshot names are not.
+    fn is(&self, server: &str, database: &str, name: &str) -> bool {
+        self.server.eq_ignore_ascii_case(server)
+            && self.database.eq_ignore_ascii_case(database)
+            && self.name == name
+    }
+}
+
+impl Catalog {
+    pub fn load() -> Result<Catalog, String> {
+        Catalog::load_from(dirs::tainer_dir().join(CATALOG_FILE))
+    }
+
+    pub fn load_from(path: PathBuf) -> Result<Catalog, String> {
+        if !path.exists() {
+            return Ok(Catalog {
+                path,
+                snapshots: Vec::new(),
+            });
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let snapshots = serde_json::from_str(&content)
+            .map_err(|e| format!("{} is not a valid snapshot catalog: {}", path.display(), e))?;
+
+        Ok(Catalog { path, snapshots })
+    }
+
+    pub fn find(&self, server: &str, database: &str, name: &str) -> Option<&Snapshot> {
+        self.snapshots
+            .iter()
+            .find(|snapshot| snapshot.is(server, database, name))
+    }
+
+    /// Adds the snapshot, replacing any with the same name for the same database.
+    pub fn add(&mut self, snapshot: Snapshot) {
+        self.snapshots
+            .retain(|existing| !existing.is(&snapshot.server, &snapshot.database, &snapshot.name));
+        self.snapshots.push(snapshot);
+    }
+
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(
+            &self.path,
+            serde_json::to_string_pretty(&self.snapshots).expect("Snapshots are valid JSON"),
+        )
+        .map_err(|e| format!("Could not write {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_catalog(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "tainer-{}-{}-{}",
+            name,
+            std::process::id(),
+            CATALOG_FILE
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn snapshot(name: &str, server: &str, file: &str) -> Snapshot {
+        Snapshot {
+            name: name.to_string(),
+            server: server.to_string(),
+            database: "dbEnvirotainerELOS".to_string(),
+            file: file.to_string(),
+            created: "2026-10-19 12:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn missing_catalog_is_empty() {
+        let catalog = Catalog::load_from(temp_catalog("missing")).unwrap();
+
+        assert!(catalog.snapshots().is_empty());
+    }
+
+    #[test]
+    fn saved_snapshots_are_found_after_loading() {
+        let path = temp_catalog("saved");
+        let mut catalog = Catalog::load_from(path.clone()).unwrap();
+        catalog.add(snapshot(
+            "before",
+            "PINKGOLD\\PINKGOLD16",
+            "D:\\Backups\\before.bak",
+        ));
+        catalog.save().unwrap();
+
+        let reloaded = Catalog::load_from(path.clone()).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let found = reloaded
+            .find("pinkgold\\pinkgold16", "DBENVIROTAINERELOS", "before")
+            .unwrap();
+        assert_eq!(found.file, "D:\\Backups\\before.bak");
+        assert!(reloaded
+            .find("PINKGOLD\\PINKGOLD16", "dbEnvirotainerELOS", "BEFORE")
+            .is_none());
+    }
+
+    #[test]
+    fn adding_a_snapshot_again_replaces_it() {
+        let mut catalog = Catalog::load_from(temp_catalog("replaced")).unwrap();
+        catalog.add(snapshot("before", "PINKGOLD", "old.bak"));
+        catalog.add(snapshot("before", "pinkgold", "new.bak"));
+        catalog.add(snapshot("before", "OTHER", "other.bak"));
+
+        assert_eq!(catalog.snapshots().len(), 2);
+        assert_eq!(
+            catalog
+                .find("PINKGOLD", "dbEnvirotainerELOS", "before")
+                .unwrap()
+                .file,
+            "new.bak"
+        );
+    }
+
+    #[test]
+    fn invalid_catalog_is_an_error() {
+        let path = temp_catalog("invalid");
+        std::fs::write(&path, "{ not json").unwrap();
+
+        let result = Catalog::load_from(path.clone());
+        let _ = std::fs::remove_file(&path);
+
+        assert!(result
+            .err()
+            .unwrap()
+            .contains("is not a valid snapshot catalog"));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    commands::{
+        create_user::{self, NewUser},
+        roles::{self, Role},
+    },
+    db::{self, SqlClient},
+    schema::{self, Table},
+};
+
+/// The user and role operations `create-user` and `setup` need, so they can run against
+/// something other than SQL Server.
+pub(crate) trait UserStore {
+    async fn begin_transaction(&mut self) -> Result<(), tiberius::error::Error>;
+
+    /// Commits the transaction if `commit` is true, rolls it back otherwise.
+    async fn end_transaction(&mut self, commit: bool) -> Result<(), tiberius::error::Error>;
+
+    async fn verify_schema(&mut self, tables: &[Table]) -> Result<(), String>;
+
+    async fn user_with_email_exists(&mut self, email: &str)
+        -> Result<bool, tiberius::error::Error>;
+
+    async fn roles(&mut self) -> Result<Vec<Role>, tiberius::error::Error>;
+
+    /// Inserts the user and returns its id.
+    async fn insert_user(&mut self, user: &NewUser) -> Result<String, tiberius::error::Error>;
+
+    async fn add_role_to_user(
+        &mut self,
+        user_id: &str,
+        role_id: &str,
+    ) -> Result<(), tiberius::error::Error>;
+}
+
+pub(crate) struct SqlUserStore {
+    client: SqlClient,
+}
+
+impl SqlUserStore {
+    pub(crate) fn new(client: SqlClient) -> SqlUserStore {
+        SqlUserStore { client }
+    }
+}
+
+impl UserStore for SqlUserStore {
+    async fn begin_transaction(&mut self) -> Result<(), tiberius::error::Error> {
+        db::begin_transaction(&mut self.client).await
+    }
+
+    async fn end_transaction(&mut self, commit: bool) -> Result<(), tiberius::error::Error> {
+        db::end_transaction(&mut self.client, commit).await
+    }
+
+    async fn verify_schema(&mut self, tables: &[Table]) -> Result<(), String> {
+        schema::verify(&mut self.client, tables).await
+    }
+
+    async fn user_with_email_exists(
+        &mut self,
+        email: &str,
+    ) -> Result<bool, tiberius::error::Error> {
+        create_user::user_with_email_exists(&mut self.client, email).await
+    }
+
+    async fn roles(&mut self) -> Result<Vec<Role>, tiberius::error::Error> {
+        roles::fetch_roles(&mut self.client).await
+    }
+
+    async fn insert_user(&mut self, user: &NewUser) -> Result<String, tiberius::error::Error> {
+        create_user::insert_user(&mut self.client, user).await
+    }
+
+    async fn add_role_to_user(
+        &mut self,
+        user_id: &str,
+        role_id: &str,
+    ) -> Result<(), tiberius::error::Error> {
+        create_user::add_role_to_user(&mut self.client, user_id, role_id).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) use memory::InMemoryUserStore;
+
+#[cfg(test)]
+mod memory {
+    use super::*;
+
+    /// Keeps users and roles in memory, with transactions that roll back to a snapshot.
+    #[derive(Default)]
+    pub(crate) struct InMemoryUserStore {
+        pub users: Vec<(String, NewUser)>,
+        pub user_roles: Vec<(String, String)>,
+        pub roles: Vec<Role>,
+        /// Error to return from the operation with this name, e.g. "add_role_to_user"
+        pub fail_on: Option<&'static str>,
+        pub schema_error: Option<String>,
+        pub commits: usize,
+        pub rollbacks: usize,
+        snapshot: Option<Snapshot>,
+    }
+
+    struct Snapshot {
+        users: Vec<(String, NewUser)>,
+        user_roles: Vec<(String, String)>,
+    }
+
+    impl InMemoryUserStore {
+        pub(crate) fn with_roles(names: &[(&str, &str)]) -> InMemoryUserStore {
+            InMemoryUserStore {
+                roles: names
+                    .iter()
+                    .map(|(id, name)| Role {
+                        id: id.to_string(),
+                        name: name.to_string(),
+                    })
+                    .collect(),
+                ..Default::default()
+            }
+        }
+
+        pub(crate) fn roles_of(&self, email: &str) -> Vec<String> {
+            let Some((user_id, _)) = self.users.iter().find(|(_, user)| user.email == email) else {
+                return Vec::new();
+            };
+            self.user_roles
+                .iter()
+                .filter(|(id, _)| id == user_id)
+                .map(|(_, role_id)| role_id.clone())
+                .collect()
+        }
+
+        fn fail(&self, operation: &str) -> Result<(), tiberius::error::Error> {
+            match self.fail_on {
+                Some(name) if name == operation => Err(tiberius::error::Error::Protocol(
+                    format!("{} failed", operation).into(),
+                )),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl UserStore for InMemoryUserStore {
+        async fn begin_transaction(&mut self) -> Result<(), tiberius::error::Error> {
+            self.fail("begin_transaction")?;
+            self.snapshot = Some(Snapshot {
+                users: self.users.clone(),
+                user_roles: self.user_roles.clone(),
+            });
+            Ok(())
+        }
+
+        async fn end_transaction(&mut self, commit: bool) -> Result<(), tiberius::error::Error> {
+            let snapshot = self.snapshot.take().expect("Transaction was begun");
+            if commit {
+                self.commits += 1;
+            } else {
+                self.users = snapshot.users;
+                self.user_roles = snapshot.user_roles;
+                self.rollbacks += 1;
+            }
+            Ok(())
+        }
+
+        async fn verify_schema(&mut self, _tables: &[Table]) -> Result<(), String> {
+            self.schema_error.clone().map_or(Ok(()), Err)
+        }
+
+        async fn user_with_email_exists(
+            &mut self,
+            email: &str,
+        ) -> Result<bool, tiberius::error::Error> {
+            self.fail("user_with_email_exists")?;
+            Ok(self.users.iter().any(|(_, user)| user.email == email))
+        }
+
+        async fn roles(&mut self) -> Result<Vec<Role>, tiberius::error::Error> {
+            self.fail("roles")?;
+            Ok(self.roles.clone())
+        }
+
+        async fn insert_user(&mut self, user: &NewUser) -> Result<String, tiberius::error::Error> {
+            self.fail("insert_user")?;
+            let id = format!("user-{}", self.users.len() + 1);
+            self.users.push((id.clone(), user.clone()));
+            Ok(id)
+        }
+
+        async fn add_role_to_user(
+            &mut self,
+            user_id: &str,
+            role_id: &str,
+        ) -> Result<(), tiberius::error::Error> {
+            self.fail("add_role_to_user")?;
+            self.user_roles
+                .push((user_id.to_string(), role_id.to_string()));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use dialoguer::Password;
+use serde::{Deserialize, Serialize};
+
+use crate::dirs;
+
+static VAULT_FILE: &str = "vault.json";
+static PASSPHRASE_ENV: &str = "TAINER_VAULT_PASSPHRASE";
+static PLACEHOLDER_START: &str = "${secret:";
+
+/// Secrets encrypted with a key derived from a passphrase (Argon2id + ChaCha20-Poly1305).
+pub struct Vault {
+    path: PathBuf,
+    passphrase: String,
+    secrets: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl Vault {
+    /// Opens the vault in `~/.tainer`, or starts a new one if there is none yet. The passphrase
+    /// is taken from `TAINER_VAULT_PASSPHRASE` or prompted for.
+    pub fn open() -> Result<Vault, String> {
+        let path = dirs::tainer_dir().join(VAULT_FILE);
+        if !path.exists() {
+            return Ok(Vault {
+                path,
+                passphrase: passphrase(true)?,
+                secrets: BTreeMap::new(),
+            });
+        }
+
+        Vault::load(path, passphrase(false)?)
+    }
+
+    /// Opens the vault in `~/.tainer` to read from it, there is nothing to read without one.
+    pub fn open_existing() -> Result<Vault, String> {
+        let path = dirs::tainer_dir().join(VAULT_FILE);
+        if !path.exists() {
+            return Err("No vault, run `tainer secret set` to create one".to_string());
+        }
+
+        Vault::load(path, passphrase(false)?)
+    }
+
+    fn load(path: PathBuf, passphrase: String) -> Result<Vault, String> {
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let file: VaultFile = serde_json::from_str(&content)
+            .map_err(|e| format!("{} is not a valid vault: {}", path.display(), e))?;
+        let secrets = decrypt(&file, &passphrase)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+
+        Ok(Vault {
+            path,
+            passphrase,
+            secrets,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.secrets.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.secrets.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.secrets.remove(name).is_some()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.secrets.keys()
+    }
+
+    /// Encrypts the secrets with a fresh salt and nonce and writes them to disk, readable only
+    /// by the current user.
+    pub fn save(&self) -> Result<(), String> {
+        let file = encrypt(&self.secrets, &self.passphrase)?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        write_private(
+            &self.path,
+            &serde_json::to_string_pretty(&file).expect("Vault is valid JSON"),
+        )
+        .map_err(|e| format!("Could not write {}: {}", self.path.display(), e))
+    }
+}
+
+fn encrypt(secrets: &BTreeMap<String, String>, passphrase: &str) -> Result<VaultFile, String> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let plaintext = serde_json::to_vec(secrets).expect("Secrets are valid JSON");
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(&nonce, plaintext.as_slice())
+        .map_err(|_| "Could not encrypt the vault".to_string())?;
+
+    Ok(VaultFile {
+        salt: to_hex(&salt),
+        nonce: to_hex(&nonce),
+        ciphertext: to_hex(&ciphertext),
+    })
+}
+
+fn decrypt(file: &VaultFile, passphrase: &str) -> Result<BTreeMap<String, String>, String> {
+    let salt = from_hex(&file.salt)?;
+    let nonce = from_hex(&file.nonce)?;
+    let ciphertext = from_hex(&file.ciphertext)?;
+    if nonce.len() != 12 {
+        return Err("The nonce is not 12 bytes".to_string());
+    }
+
+    let plaintext = cipher(passphrase, &salt)?
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| "Wrong passphrase, or the vault has been tampered with".to_string())?;
+    serde_json::from_slice(&plaintext)
+        .map_err(|e| format!("Could not read the secrets in the vault: {}", e))
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    use std::{
+        fs::{OpenOptions, Permissions},
+        io::Write,
+        os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // the mode only applies to new files, vaults written by older versions are fixed here
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(content.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    std::fs::write(path, content)
+}
+
+/// Replaces every `${secret:<name>}` placeholder with the secret from the vault. The vault is
+/// only opened if there are placeholders to resolve.
+pub fn resolve(text: &str) -> Result<String, String> {
+    if !text.contains(PLACEHOLDER_START) {
+        return Ok(text.to_string());
+    }
+
+    let vault = Vault::open_existing()?;
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let after_start = &rest[start + PLACEHOLDER_START.len()..];
+        let end = after_start
+            .find('}')
+            .ok_or_else(|| format!("Unterminated secret placeholder in {}", rest))?;
+        let name = &after_start[..end];
+        let value = vault
+            .get(name)
+            .ok_or_else(|| format!("No secret named {} in the vault", name))?;
+
+        result.push_str(&rest[..start]);
+        result.push_str(value);
+        rest = &after_start[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn passphrase(new: bool) -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    let prompt = Password::new().with_prompt("Vault passphrase");
+    let prompt = if new {
+        prompt.with_confirmation("Repeat passphrase", "The passphrases don't match")
+    } else {
+        prompt
+    };
+    prompt
+        .interact()
+        .map_err(|e| format!("Could not read passphrase: {}", e))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, String> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Could not derive key from passphrase: {}", e))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err("Invalid hex in vault".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| "Invalid hex in vault".to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("sql-sa".to_string(), "hunter2".to_string()),
+            ("azure".to_string(), "ö;=}".to_string()),
+        ])
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let file = encrypt(&secrets(), "correct horse").unwrap();
+
+        assert_eq!(decrypt(&file, "correct horse").unwrap(), secrets());
+    }
+
+    #[test]
+    fn rejects_the_wrong_passphrase() {
+        let file = encrypt(&secrets(), "correct horse").unwrap();
+
+        assert_eq!(
+            decrypt(&file, "battery staple").unwrap_err(),
+            "Wrong passphrase, or the vault has been tampered with"
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertext() {
+        let mut file = encrypt(&secrets(), "correct horse").unwrap();
+        let flipped = if file.ciphertext.starts_with('0') {
+            "1"
+        } else {
+            "0"
+        };
+        file.ciphertext.replace_range(..1, flipped);
+
+        assert_eq!(
+            decrypt(&file, "correct horse").unwrap_err(),
+            "Wrong passphrase, or the vault has been tampered with"
+        );
+    }
+
+    #[test]
+    fn saves_and_loads_the_vault() {
+        let path = std::env::temp_dir().join(format!("tainer-vault-{}.json", std::process::id()));
+        let vault = Vault {
+            path: path.clone(),
+            passphrase: "correct horse".to_string(),
+            secrets: secrets(),
+        };
+
+        vault.save().unwrap();
+        let loaded = Vault::load(path.clone(), "correct horse".to_string());
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777
+        };
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap().secrets, secrets());
+        #[cfg(unix)]
+        assert_eq!(mode, 0o600);
+    }
+}
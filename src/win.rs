@@ -1,11 +1,111 @@
-use std::process::Command;
+use std::{
+    cell::{OnceCell, RefCell},
+    path::Path,
+    process::Command,
+};
 
-pub fn msmq_enabled() -> bool {
-    if !is_admin_shell() {
-        panic!("Please run this command in an admin shell.");
+use serde::Deserialize;
+
+use crate::{
+    platform::{env_var, Platform, Queue},
+    shell::{PowerShellSession, Shell, ShellOutput},
+};
+
+/// Windows, through one PowerShell session that starts on first use. What can't change while
+/// tainer runs is only asked once.
+pub(crate) struct Windows<S = PowerShellSession> {
+    start: fn() -> Result<S, String>,
+    shell: RefCell<Option<S>>,
+    admin: OnceCell<bool>,
+}
+
+impl Windows {
+    pub(crate) fn new() -> Windows {
+        Windows::with_shell(PowerShellSession::start)
+    }
+}
+
+impl<S: Shell> Windows<S> {
+    pub(crate) fn with_shell(start: fn() -> Result<S, String>) -> Windows<S> {
+        Windows {
+            start,
+            shell: RefCell::new(None),
+            admin: OnceCell::new(),
+        }
+    }
+
+    fn run(&self, command: &str) -> Result<ShellOutput, String> {
+        let mut shell = self.shell.borrow_mut();
+        if shell.is_none() {
+            *shell = Some((self.start)()?);
+        }
+        shell.as_mut().expect("Shell was started").run(command)
+    }
+
+    fn is_admin_shell(&self) -> Result<bool, String> {
+        if let Some(admin) = self.admin.get() {
+            return Ok(*admin);
+        }
+
+        let powershell_command = r#"
+    $isAdmin = ([Security.Principal.WindowsPrincipal][Security.Principal.WindowsIdentity]::GetCurrent()).IsInRole([Security.Principal.WindowsBuiltInRole]::Administrator)
+    if ($isAdmin) {
+        Write-Output "Admin shell"
+    } else {
+        Write-Output "Non-admin shell"
+    }
+    "#;
+        let admin = self.run(powershell_command)?.stdout.contains("Admin shell");
+        Ok(*self.admin.get_or_init(|| admin))
     }
 
-    let powershell_command = r#"
+    /// Runs an MSMQ cmdlet, whose errors end up in the output.
+    fn msmq(&self, command: &str) -> Result<String, String> {
+        let output = self.run(command)?;
+        if output.success {
+            Ok(output.stdout)
+        } else {
+            Err(output.stdout.trim().to_string())
+        }
+    }
+
+    /// Pipes the private queue named `name` into `command`. `-Name` treats `*` and `[]` as
+    /// wildcards, so the queue is picked by comparing names, the same way `queues` names them.
+    fn msmq_queue(&self, name: &str, command: &str) -> Result<(), String> {
+        let name = quote(name);
+        self.msmq(&format!(
+            "$queue = @(Get-MsmqQueue -QueueType Private | Where-Object {{ ($_.QueueName -split '\\\\')[-1] -eq {0} }}); if ($queue.Count -eq 0) {{ throw ('No queue named ' + {0}) }}; $queue | {1} | Out-Null",
+            name, command
+        ))
+        .map(|_| ())
+    }
+}
+
+/// A queue as `Get-MsmqQueue` describes it.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MsmqQueue {
+    queue_name: String,
+    transactional: bool,
+    message_count: u64,
+}
+
+/// Quotes a string for PowerShell, nothing inside single quotes is expanded.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+impl<S: Shell> Platform for Windows<S> {
+    fn has_msmq(&self) -> bool {
+        true
+    }
+
+    fn msmq_enabled(&self) -> Result<bool, String> {
+        if !self.is_admin_shell()? {
+            return Err("Please run this command in an admin shell.".to_string());
+        }
+
+        let powershell_command = r#"
     $dismOutput = (dism /online /get-features /format:table | Select-String -Pattern "MSMQ")
 
     if ($dismOutput -match "MSMQ.*Enable") {
@@ -14,28 +114,28 @@ pub fn msmq_enabled() -> bool {
         Write-Output "MSMQ is not enabled."
     }
     "#;
-    let output = Command::new("powershell")
-        .args(["-NoProfile", "-Command", powershell_command])
-        .output()
-        .expect("Failed to execute PowerShell command")
-        .stdout;
-
-    String::from_utf8(output)
-        .expect("Failed to convert output to string")
-        .contains("MSMQ is enabled.")
-}
-
-pub fn enable_msmq() -> Result<(), String> {
-    if !is_admin_shell() {
-        return Err("Please run this command in an admin shell.".to_string());
+        let output = self.run(powershell_command)?;
+        if !output.success {
+            return Err(format!(
+                "Could not list the Windows features: {}",
+                output.stdout.trim()
+            ));
+        }
+        Ok(output.stdout.contains("MSMQ is enabled."))
     }
 
-    // let enable_msmq_command = r#"
-    // Enable-WindowsOptionalFeature -Online -FeatureName MSMQ-Server -All -NoRestart
-    // "#;
+    /// Enables MSMQ-Server and the optional MSMQ features one at a time. In its own process
+    /// like the scripts, so the progress of the long running commands is shown.
+    ///
+    /// Enabling the optional features beyond MSMQ-Server hasn't been verified on a clean
+    /// Windows install yet. Some of them (e.g. MSMQ-ADIntegration, MSMQ-RoutingServer) may
+    /// not exist on every Windows edition.
+    fn enable_msmq(&self) -> Result<(), String> {
+        if !self.is_admin_shell()? {
+            return Err("Please run this command in an admin shell.".to_string());
+        }
 
-    // need to test this!
-    let enable_msmq_command = r#"
+        let enable_msmq_command = r#"
     $features = @(
         'MSMQ-Server',
         'MSMQ-Services',
@@ -51,79 +151,212 @@ pub fn enable_msmq() -> Result<(), String> {
     }
     "#;
 
-    let enable_status = Command::new("powershell")
-        .args(["-NoProfile", "-Command", enable_msmq_command])
-        .status()
-        .expect("Failed to execute PowerShell command");
+        let enable_status = Command::new("powershell")
+            .args(["-NoProfile", "-Command", enable_msmq_command])
+            .status()
+            .map_err(|e| format!("Failed to execute PowerShell command: {}", e))?;
 
-    if enable_status.success() {
-        Ok(())
-    } else {
-        Err("Failed to enable MSMQ.".to_string())
+        if enable_status.success() {
+            Ok(())
+        } else {
+            Err("Failed to enable MSMQ.".to_string())
+        }
     }
-}
 
-pub fn execute_ps1(script_path: &str, args: &[&str]) -> Result<(), String> {
-    let script_args: Vec<&str> = vec!["-File", script_path];
-    let script_args = script_args
-        .iter()
-        .chain(args.iter())
-        .cloned()
-        .collect::<Vec<&str>>();
-
-    let script_status = Command::new("powershell")
-        .args(script_args)
-        .status()
-        .expect("Failed to execute PowerShell command");
-
-    if script_status.success() {
-        Ok(())
-    } else {
-        Err("Failed to execute PowerShell script.".to_string())
+    fn execute_ps1(&self, script_path: &Path, args: &[&str]) -> Result<(), String> {
+        let script_status = Command::new("powershell")
+            .arg("-File")
+            .arg(script_path)
+            .args(args)
+            .status()
+            .map_err(|e| format!("Failed to execute PowerShell command: {}", e))?;
+
+        if script_status.success() {
+            Ok(())
+        } else {
+            Err("Failed to execute PowerShell script.".to_string())
+        }
     }
-}
 
-pub fn get_computer_name() -> String {
-    let output = Command::new("powershell")
-        .args(["-NoProfile", "-Command", "Write-Output $env:COMPUTERNAME"])
-        .output()
-        .expect("Failed to execute PowerShell command")
-        .stdout;
-
-    String::from_utf8(output)
-        .expect("Failed to convert output to string")
-        .trim()
-        .to_string()
-}
+    /// Windows always sets `COMPUTERNAME` and `USERNAME`, so there is no need for PowerShell.
+    fn computer_name(&self) -> String {
+        env_var(&["COMPUTERNAME"]).unwrap_or_else(|| "localhost".to_string())
+    }
+
+    fn account_name(&self) -> String {
+        env_var(&["USERNAME"]).unwrap_or_default()
+    }
+
+    /// The system queues, whose names end with `$`, are left out.
+    fn queues(&self) -> Result<Vec<Queue>, String> {
+        let command = r#"
+    $queues = @(Get-MsmqQueue -QueueType Private | Select-Object QueueName, Transactional, MessageCount)
+    ConvertTo-Json -Compress -InputObject $queues
+    "#;
+        let output = self.msmq(command)?;
+        let queues: Vec<MsmqQueue> = serde_json::from_str(output.trim())
+            .map_err(|e| format!("Could not read the queues: {}", e))?;
+        Ok(queues
+            .into_iter()
+            .map(|queue| {
+                let name = queue.queue_name.rsplit('\\').next().unwrap_or_default();
+                Queue {
+                    name: name.to_string(),
+                    transactional: queue.transactional,
+                    messages: queue.message_count,
+                }
+            })
+            .filter(|queue| !queue.name.ends_with('$'))
+            .collect())
+    }
+
+    fn create_queue(&self, name: &str, transactional: bool) -> Result<(), String> {
+        self.msmq(&format!(
+            "New-MsmqQueue -QueueType Private -Name {}{} | Out-Null",
+            quote(name),
+            if transactional { " -Transactional" } else { "" }
+        ))
+        .map(|_| ())
+    }
+
+    fn purge_queue(&self, name: &str) -> Result<(), String> {
+        self.msmq_queue(name, "Clear-MsmqQueue")
+    }
 
-pub fn get_account_name() -> String {
-    let output = Command::new("powershell")
-        .args(["-NoProfile", "-Command", "Write-Output $env:USERNAME"])
-        .output()
-        .expect("Failed to execute PowerShell command")
-        .stdout;
-
-    String::from_utf8(output)
-        .expect("Failed to convert output to string")
-        .trim()
-        .to_string()
+    fn delete_queue(&self, name: &str) -> Result<(), String> {
+        self.msmq_queue(name, "Remove-MsmqQueue")
+    }
+
+    fn grant_queue_access(&self, name: &str, account: &str) -> Result<(), String> {
+        self.msmq_queue(
+            name,
+            &format!(
+                "Set-MsmqQueueACL -UserName {} -Allow FullControl",
+                quote(account)
+            ),
+        )
+    }
 }
 
-fn is_admin_shell() -> bool {
-    let powershell_command = r#"
-    $isAdmin = ([Security.Principal.WindowsPrincipal][Security.Principal.WindowsIdentity]::GetCurrent()).IsInRole([Security.Principal.WindowsBuiltInRole]::Administrator)
-    if ($isAdmin) {
-        Write-Output "Admin shell"
-    } else {
-        Write-Output "Non-admin shell"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::FakeShell;
+
+    fn windows(start: fn() -> Result<FakeShell, String>) -> Windows<FakeShell> {
+        Windows::with_shell(start)
+    }
+
+    fn commands(windows: &Windows<FakeShell>) -> Vec<String> {
+        windows
+            .shell
+            .borrow()
+            .as_ref()
+            .map(|shell| shell.commands.clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn reads_the_names_without_a_shell() {
+        let windows = windows(|| Err("PowerShell is not installed".to_string()));
+
+        assert_eq!(
+            windows.account_name(),
+            env_var(&["USERNAME"]).unwrap_or_default()
+        );
+        assert!(!windows.computer_name().is_empty());
+        assert!(windows.shell.borrow().is_none());
+    }
+
+    #[test]
+    fn checks_for_admin_once() {
+        let windows = windows(|| {
+            Ok(FakeShell::new(&[
+                ("IsInRole", "Admin shell"),
+                ("dism", "MSMQ is not enabled."),
+            ]))
+        });
+
+        assert_eq!(windows.msmq_enabled(), Ok(false));
+        assert_eq!(windows.msmq_enabled(), Ok(false));
+
+        let commands = commands(&windows);
+        assert_eq!(commands.len(), 3);
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|command| command.contains("IsInRole"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn msmq_needs_an_admin_shell() {
+        let windows = windows(|| Ok(FakeShell::new(&[("IsInRole", "Non-admin shell")])));
+
+        let error = windows.msmq_enabled().unwrap_err();
+
+        assert!(error.contains("admin shell"), "{}", error);
+        assert!(!commands(&windows)
+            .iter()
+            .any(|command| command.contains("dism")));
+    }
+
+    #[test]
+    fn lists_the_private_queues_without_the_system_queues() {
+        let windows = windows(|| {
+            Ok(FakeShell::new(&[(
+                "Get-MsmqQueue",
+                r#"[{"QueueName":"private$\\elos.audit","Transactional":true,"MessageCount":3},{"QueueName":"private$\\admin_queue$","Transactional":false,"MessageCount":0}]"#,
+            )]))
+        });
+
+        assert_eq!(
+            windows.queues(),
+            Ok(vec![Queue {
+                name: "elos.audit".to_string(),
+                transactional: true,
+                messages: 3,
+            }])
+        );
+    }
+
+    #[test]
+    fn quotes_queue_names_and_accounts() {
+        let windows = windows(|| Ok(FakeShell::new(&[("Set-MsmqQueueACL", "")])));
+
+        windows.grant_queue_access("elos.audit", "O'Brien").unwrap();
+
+        assert_eq!(
+            commands(&windows),
+            ["$queue = @(Get-MsmqQueue -QueueType Private | Where-Object { ($_.QueueName -split '\\\\')[-1] -eq 'elos.audit' }); if ($queue.Count -eq 0) { throw ('No queue named ' + 'elos.audit') }; $queue | Set-MsmqQueueACL -UserName 'O''Brien' -Allow FullControl | Out-Null"]
+        );
+    }
+
+    #[test]
+    fn picks_queues_by_name_without_wildcards() {
+        let windows = windows(|| Ok(FakeShell::new(&[("Remove-MsmqQueue", "")])));
+
+        windows.delete_queue("elos.[audit]*").unwrap();
+
+        let commands = commands(&windows);
+        assert!(!commands[0].contains("-Name"), "{}", commands[0]);
+        assert!(
+            commands[0].contains("-eq 'elos.[audit]*'"),
+            "{}",
+            commands[0]
+        );
+    }
+
+    #[test]
+    fn starts_the_shell_on_first_use() {
+        let windows = windows(|| Err("Failed to start powershell".to_string()));
+
+        assert!(windows.shell.borrow().is_none());
+        assert_eq!(
+            windows.msmq_enabled(),
+            Err("Failed to start powershell".to_string())
+        );
     }
-    "#;
-    let output = Command::new("powershell")
-        .args(["-NoProfile", "-Command", powershell_command])
-        .output()
-        .expect("Failed to execute PowerShell command");
-
-    String::from_utf8(output.stdout)
-        .expect("Failed to convert output to string")
-        .contains("Admin shell")
 }
@@ -0,0 +1,213 @@
+//! A fake SQL Server for end-to-end tests of the database commands. It speaks just enough TDS
+//! for tiberius to log in without TLS, records every statement it receives and answers them
+//! with scripted replies.
+
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+mod tds;
+
+/// A value in a scripted row or a parameter the client sent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Int(i64),
+    Bit(bool),
+    Text(String),
+    /// Lowercase and hyphenated, like `Uuid::to_string`
+    Guid(String),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bit(value)
+    }
+}
+
+/// What the client sent, in the order it was sent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    /// A statement run without parameters (`simple_query`)
+    Batch(String),
+    /// A statement run through `sp_executesql` (`query` and `execute`), with its `@P1`..
+    /// parameters
+    Rpc {
+        statement: String,
+        params: Vec<Value>,
+    },
+}
+
+impl Request {
+    pub fn sql(&self) -> &str {
+        match self {
+            Request::Batch(sql) => sql,
+            Request::Rpc { statement, .. } => statement,
+        }
+    }
+
+    pub fn params(&self) -> &[Value] {
+        match self {
+            Request::Batch(_) => &[],
+            Request::Rpc { params, .. } => params,
+        }
+    }
+}
+
+/// The login of a connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Login {
+    pub user: String,
+    pub database: String,
+}
+
+/// A result set with the column types taken from the first non-NULL value in each column.
+#[derive(Clone, Debug, Default)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultSet {
+    pub fn new(columns: &[&str]) -> ResultSet {
+        ResultSet {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn row<V: Into<Value>>(mut self, values: impl IntoIterator<Item = V>) -> ResultSet {
+        let row = values.into_iter().map(Into::into).collect::<Vec<_>>();
+        assert_eq!(
+            row.len(),
+            self.columns.len(),
+            "Row has the wrong number of values"
+        );
+        self.rows.push(row);
+        self
+    }
+}
+
+/// The answer to a statement.
+#[derive(Clone, Debug)]
+pub enum Reply {
+    Rows(ResultSet),
+    /// No result set, with this many rows affected
+    Done(u64),
+    /// Fails the statement like SQL Server would, e.g. 2627 for a duplicate key
+    Error {
+        code: u32,
+        message: String,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    replies: Vec<(String, Reply)>,
+    requests: Vec<Request>,
+    logins: Vec<Login>,
+}
+
+/// Listens on a free port on localhost for as long as the test runs, accepting any login.
+pub struct FakeServer {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeServer {
+    pub fn start() -> FakeServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind the fake server");
+        let port = listener.local_addr().expect("Bound to a port").port();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let shared = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = shared.clone();
+                std::thread::spawn(move || serve(stream, state));
+            }
+        });
+
+        FakeServer { port, state }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// A connection string for the fake server, without encryption since it doesn't do TLS.
+    pub fn connection_string(&self, database: &str) -> String {
+        format!(
+            "Server=tcp:127.0.0.1,{};Database={};User Id=sa;Password=fake;Encrypt=DANGER_PLAINTEXT",
+            self.port, database
+        )
+    }
+
+    /// Answers statements containing `pattern` with `reply`. The first matching pattern wins,
+    /// statements that match none get `Reply::Done(0)`.
+    pub fn reply(&self, pattern: &str, reply: Reply) {
+        self.state
+            .lock()
+            .unwrap()
+            .replies
+            .push((pattern.to_string(), reply));
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn logins(&self) -> Vec<Login> {
+        self.state.lock().unwrap().logins.clone()
+    }
+}
+
+fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    // a client that disconnects or sends something unsupported ends the connection, which the
+    // test sees as a failed command
+    while let Ok(Some(message)) = tds::read_message(&mut stream) {
+        let response = match message {
+            tds::Message::PreLogin => tds::prelogin_response(),
+            tds::Message::Login(login) => {
+                state.lock().unwrap().logins.push(login);
+                tds::login_response()
+            }
+            tds::Message::Request(request) => {
+                let reply = {
+                    let mut state = state.lock().unwrap();
+                    state.requests.push(request.clone());
+                    state
+                        .replies
+                        .iter()
+                        .find(|(pattern, _)| request.sql().contains(pattern.as_str()))
+                        .map(|(_, reply)| reply.clone())
+                        .unwrap_or(Reply::Done(0))
+                };
+                tds::reply_response(&reply)
+            }
+            tds::Message::Attention => tds::attention_response(),
+        };
+        if tds::write_message(&mut stream, &response).is_err() {
+            return;
+        }
+    }
+}
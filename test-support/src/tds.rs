@@ -0,0 +1,494 @@
+//! The parts of TDS 7.4 (MS-TDS) that tiberius uses for plain text connections.
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{Login, Reply, Request, Value};
+
+const SQL_BATCH: u8 = 0x01;
+const RPC: u8 = 0x03;
+const TABULAR_RESULT: u8 = 0x04;
+const ATTENTION: u8 = 0x06;
+const LOGIN7: u8 = 0x10;
+const PRELOGIN: u8 = 0x12;
+
+const END_OF_MESSAGE: u8 = 0x01;
+const HEADER_LEN: usize = 8;
+const MAX_PACKET_LEN: usize = 4096;
+
+const COLMETADATA: u8 = 0x81;
+const ERROR: u8 = 0xAA;
+const LOGINACK: u8 = 0xAD;
+const ROW: u8 = 0xD1;
+const DONE: u8 = 0xFD;
+
+const DONE_ERROR: u16 = 0x02;
+const DONE_COUNT: u16 = 0x10;
+const DONE_ATTN: u16 = 0x20;
+
+const NULL_TYPE: u8 = 0x1F;
+const INT1: u8 = 0x30;
+const BIT: u8 = 0x32;
+const INT2: u8 = 0x34;
+const INT4: u8 = 0x38;
+const INT8: u8 = 0x7F;
+const GUIDN: u8 = 0x24;
+const INTN: u8 = 0x26;
+const BITN: u8 = 0x68;
+const NVARCHAR: u8 = 0xE7;
+const NCHAR: u8 = 0xEF;
+
+/// Latin1_General_CI_AS
+const COLLATION: [u8; 5] = [0x09, 0x04, 0xD0, 0x00, 0x34];
+
+const TDS_7_4: u32 = 0x7400_0004;
+
+pub enum Message {
+    PreLogin,
+    Login(Login),
+    Request(Request),
+    Attention,
+}
+
+/// Reads the packets of one message, `None` when the client disconnected.
+pub fn read_message(stream: &mut TcpStream) -> io::Result<Option<Message>> {
+    let mut payload = Vec::new();
+    let kind = loop {
+        let mut header = [0u8; HEADER_LEN];
+        match stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut packet = vec![0u8; len.saturating_sub(HEADER_LEN)];
+        stream.read_exact(&mut packet)?;
+        payload.extend_from_slice(&packet);
+        if header[1] & END_OF_MESSAGE != 0 {
+            break header[0];
+        }
+    };
+
+    let message = match kind {
+        PRELOGIN => Message::PreLogin,
+        LOGIN7 => Message::Login(parse_login(&payload)?),
+        SQL_BATCH => {
+            let mut reader = Reader::new(&payload);
+            reader.skip_all_headers()?;
+            Message::Request(Request::Batch(utf16(reader.rest())?))
+        }
+        RPC => Message::Request(parse_rpc(&payload)?),
+        ATTENTION => Message::Attention,
+        kind => return Err(invalid(format!("Unsupported packet type 0x{:02X}", kind))),
+    };
+    Ok(Some(message))
+}
+
+/// Writes a tabular result, split into packets the client accepts.
+pub fn write_message(stream: &mut TcpStream, payload: &[u8]) -> io::Result<()> {
+    let chunks = payload
+        .chunks(MAX_PACKET_LEN - HEADER_LEN)
+        .collect::<Vec<_>>();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let status = if i + 1 == chunks.len() {
+            END_OF_MESSAGE
+        } else {
+            0
+        };
+        let len = (chunk.len() + HEADER_LEN) as u16;
+        let mut packet = vec![TABULAR_RESULT, status];
+        packet.extend_from_slice(&len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, (i + 1) as u8, 0]);
+        packet.extend_from_slice(chunk);
+        stream.write_all(&packet)?;
+    }
+    stream.flush()
+}
+
+/// Version, encryption not supported, no instance, no thread id and no MARS.
+pub fn prelogin_response() -> Vec<u8> {
+    let options: [(u8, Vec<u8>); 5] = [
+        (0x00, vec![16, 0, 0x03, 0xE8, 0, 0]),
+        (0x01, vec![0x02]),
+        (0x02, vec![0]),
+        (0x03, vec![]),
+        (0x04, vec![0]),
+    ];
+
+    let mut offset = options.len() * 5 + 1;
+    let mut header = Vec::new();
+    let mut data = Vec::new();
+    for (token, value) in &options {
+        header.push(*token);
+        header.extend_from_slice(&(offset as u16).to_be_bytes());
+        header.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value);
+        offset += value.len();
+    }
+    header.push(0xFF);
+    header.extend_from_slice(&data);
+    header
+}
+
+pub fn login_response() -> Vec<u8> {
+    let mut ack = vec![1];
+    ack.extend_from_slice(&TDS_7_4.to_be_bytes());
+    b_varchar(&mut ack, "tainer fake server");
+    ack.extend_from_slice(&[16, 0, 0x03, 0xE8]);
+
+    let mut response = vec![LOGINACK];
+    response.extend_from_slice(&(ack.len() as u16).to_le_bytes());
+    response.extend_from_slice(&ack);
+    done(&mut response, 0, 0);
+    response
+}
+
+pub fn reply_response(reply: &Reply) -> Vec<u8> {
+    let mut response = Vec::new();
+    match reply {
+        Reply::Rows(result_set) => {
+            let types = (0..result_set.columns.len())
+                .map(|i| {
+                    result_set
+                        .rows
+                        .iter()
+                        .map(|row| &row[i])
+                        .find(|value| **value != Value::Null)
+                        .map_or(NVARCHAR, column_type)
+                })
+                .collect::<Vec<_>>();
+
+            response.push(COLMETADATA);
+            response.extend_from_slice(&(result_set.columns.len() as u16).to_le_bytes());
+            for (name, kind) in result_set.columns.iter().zip(&types) {
+                // user type, then flags with nullable set
+                response.extend_from_slice(&[0, 0, 0, 0, 0x01, 0x00]);
+                type_info(&mut response, *kind);
+                b_varchar(&mut response, name);
+            }
+            for row in &result_set.rows {
+                response.push(ROW);
+                for (value, kind) in row.iter().zip(&types) {
+                    column_value(&mut response, *kind, value);
+                }
+            }
+            done(&mut response, DONE_COUNT, result_set.rows.len() as u64);
+        }
+        Reply::Done(rows) => done(&mut response, DONE_COUNT, *rows),
+        Reply::Error { code, message } => {
+            let mut error = Vec::new();
+            error.extend_from_slice(&code.to_le_bytes());
+            // state and severity
+            error.extend_from_slice(&[1, 16]);
+            us_varchar(&mut error, message);
+            b_varchar(&mut error, "FAKE");
+            b_varchar(&mut error, "");
+            error.extend_from_slice(&1u32.to_le_bytes());
+
+            response.push(ERROR);
+            response.extend_from_slice(&(error.len() as u16).to_le_bytes());
+            response.extend_from_slice(&error);
+            done(&mut response, DONE_ERROR, 0);
+        }
+    }
+    response
+}
+
+pub fn attention_response() -> Vec<u8> {
+    let mut response = Vec::new();
+    done(&mut response, DONE_ATTN, 0);
+    response
+}
+
+fn done(response: &mut Vec<u8>, status: u16, rows: u64) {
+    response.push(DONE);
+    response.extend_from_slice(&status.to_le_bytes());
+    response.extend_from_slice(&[0, 0]);
+    response.extend_from_slice(&rows.to_le_bytes());
+}
+
+fn column_type(value: &Value) -> u8 {
+    match value {
+        Value::Int(_) => INTN,
+        Value::Bit(_) => BITN,
+        Value::Guid(_) => GUIDN,
+        Value::Text(_) | Value::Null => NVARCHAR,
+    }
+}
+
+fn type_info(response: &mut Vec<u8>, kind: u8) {
+    response.push(kind);
+    match kind {
+        INTN => response.push(4),
+        BITN => response.push(1),
+        GUIDN => response.push(16),
+        _ => {
+            response.extend_from_slice(&8000u16.to_le_bytes());
+            response.extend_from_slice(&COLLATION);
+        }
+    }
+}
+
+fn column_value(response: &mut Vec<u8>, kind: u8, value: &Value) {
+    match (kind, value) {
+        (NVARCHAR, Value::Null) => response.extend_from_slice(&0xFFFFu16.to_le_bytes()),
+        (_, Value::Null) => response.push(0),
+        (INTN, Value::Int(int)) => {
+            let int = i32::try_from(*int).expect("Result set integers are int, not bigint");
+            response.push(4);
+            response.extend_from_slice(&int.to_le_bytes());
+        }
+        (BITN, Value::Bit(bit)) => response.extend_from_slice(&[1, *bit as u8]),
+        (GUIDN, Value::Guid(guid)) => {
+            response.push(16);
+            response.extend_from_slice(&guid_bytes(guid));
+        }
+        (NVARCHAR, Value::Text(text)) => {
+            let bytes = text
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>();
+            response.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+            response.extend_from_slice(&bytes);
+        }
+        (_, value) => panic!("{:?} in a column with values of another type", value),
+    }
+}
+
+fn b_varchar(response: &mut Vec<u8>, text: &str) {
+    let units = text.encode_utf16().collect::<Vec<_>>();
+    response.push(units.len() as u8);
+    response.extend(units.iter().flat_map(|unit| unit.to_le_bytes()));
+}
+
+fn us_varchar(response: &mut Vec<u8>, text: &str) {
+    let units = text.encode_utf16().collect::<Vec<_>>();
+    response.extend_from_slice(&(units.len() as u16).to_le_bytes());
+    response.extend(units.iter().flat_map(|unit| unit.to_le_bytes()));
+}
+
+/// The user name and database from the offset and length table of a LOGIN7 record.
+fn parse_login(payload: &[u8]) -> io::Result<Login> {
+    let field = |table_offset: usize| -> io::Result<String> {
+        let mut reader = Reader::new(payload);
+        reader.skip(table_offset)?;
+        let offset = reader.u16()? as usize;
+        let len = reader.u16()? as usize * 2;
+        let bytes = payload
+            .get(offset..offset + len)
+            .ok_or_else(|| invalid("LOGIN7 field is out of bounds".to_string()))?;
+        utf16(bytes)
+    };
+
+    Ok(Login {
+        user: field(40)?,
+        database: field(68)?,
+    })
+}
+
+fn parse_rpc(payload: &[u8]) -> io::Result<Request> {
+    let mut reader = Reader::new(payload);
+    reader.skip_all_headers()?;
+    let name_len = reader.u16()?;
+    if name_len == 0xFFFF {
+        let proc_id = reader.u16()?;
+        if proc_id != 10 {
+            return Err(invalid(format!("Unsupported procedure id {}", proc_id)));
+        }
+    } else {
+        let name = utf16(reader.take(name_len as usize * 2)?)?;
+        return Err(invalid(format!("Unsupported procedure {}", name)));
+    }
+    // option flags
+    reader.u16()?;
+
+    let mut params = Vec::new();
+    while !reader.rest().is_empty() {
+        let name_len = reader.u8()? as usize;
+        reader.take(name_len * 2)?;
+        // status flags
+        reader.u8()?;
+        params.push(reader.value()?);
+    }
+
+    // sp_executesql gets the statement, the parameter declarations and then the parameters
+    let mut params = params.into_iter();
+    let statement = match params.next() {
+        Some(Value::Text(statement)) => statement,
+        other => return Err(invalid(format!("Expected a statement, got {:?}", other))),
+    };
+    params.next();
+    Ok(Request::Rpc {
+        statement,
+        params: params.collect(),
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("Message ended early".to_string()));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+
+    fn int(&mut self, len: usize) -> io::Result<Value> {
+        let bytes = self.take(len)?;
+        Ok(Value::Int(match len {
+            1 => bytes[0] as i64,
+            2 => i16::from_le_bytes(bytes.try_into().expect("2 bytes")) as i64,
+            4 => i32::from_le_bytes(bytes.try_into().expect("4 bytes")) as i64,
+            8 => i64::from_le_bytes(bytes.try_into().expect("8 bytes")),
+            len => return Err(invalid(format!("Invalid integer length {}", len))),
+        }))
+    }
+
+    /// ALL_HEADERS starts with its own total length.
+    fn skip_all_headers(&mut self) -> io::Result<()> {
+        let len = self.u32()? as usize;
+        self.skip(len.saturating_sub(4))
+    }
+
+    /// A TYPE_INFO followed by a value of that type.
+    fn value(&mut self) -> io::Result<Value> {
+        match self.u8()? {
+            NULL_TYPE => Ok(Value::Null),
+            INT1 => self.int(1),
+            INT2 => self.int(2),
+            INT4 => self.int(4),
+            INT8 => self.int(8),
+            BIT => Ok(Value::Bit(self.u8()? != 0)),
+            INTN => {
+                self.u8()?;
+                match self.u8()? as usize {
+                    0 => Ok(Value::Null),
+                    len => self.int(len),
+                }
+            }
+            BITN => {
+                self.u8()?;
+                match self.u8()? {
+                    0 => Ok(Value::Null),
+                    _ => Ok(Value::Bit(self.u8()? != 0)),
+                }
+            }
+            GUIDN => {
+                self.u8()?;
+                match self.u8()? {
+                    0 => Ok(Value::Null),
+                    _ => Ok(Value::Guid(guid_text(self.take(16)?))),
+                }
+            }
+            NVARCHAR | NCHAR => {
+                let max_len = self.u16()?;
+                self.skip(COLLATION.len())?;
+                if max_len == 0xFFFF {
+                    self.plp_text()
+                } else {
+                    match self.u16()? {
+                        0xFFFF => Ok(Value::Null),
+                        len => Ok(Value::Text(utf16(self.take(len as usize)?)?)),
+                    }
+                }
+            }
+            kind => Err(invalid(format!(
+                "Unsupported parameter type 0x{:02X}",
+                kind
+            ))),
+        }
+    }
+
+    /// nvarchar(max) comes in chunks, each with its length, ending with an empty one.
+    fn plp_text(&mut self) -> io::Result<Value> {
+        let total = self.take(8)?;
+        if total == [0xFF; 8] {
+            return Ok(Value::Null);
+        }
+        let mut bytes = Vec::new();
+        loop {
+            let len = self.u32()? as usize;
+            if len == 0 {
+                break;
+            }
+            bytes.extend_from_slice(self.take(len)?);
+        }
+        Ok(Value::Text(utf16(&bytes)?))
+    }
+}
+
+fn utf16(bytes: &[u8]) -> io::Result<String> {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect::<Vec<_>>();
+    String::from_utf16(&units).map_err(|_| invalid("Invalid UTF-16".to_string()))
+}
+
+/// SQL Server stores the first three groups of a GUID little-endian.
+const GUID_ORDER: [usize; 16] = [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15];
+
+fn guid_text(bytes: &[u8]) -> String {
+    let hex = GUID_ORDER
+        .iter()
+        .map(|&i| format!("{:02x}", bytes[i]))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn guid_bytes(text: &str) -> [u8; 16] {
+    let hex = text.replace('-', "");
+    let mut ordered = [0u8; 16];
+    for (i, byte) in ordered.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).expect("A valid GUID");
+    }
+    let mut bytes = [0u8; 16];
+    for (i, &from) in GUID_ORDER.iter().enumerate() {
+        bytes[from] = ordered[i];
+    }
+    bytes
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
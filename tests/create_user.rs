@@ -0,0 +1,367 @@
+use std::{path::PathBuf, process::Output};
+
+use tainer_test_support::{FakeServer, Reply, Request, ResultSet, Value};
+
+static ALL_FEATURES_ROLE_ID: &str = "FE4DA52F-2104-424D-B1C1-B07400E64A44";
+
+/// The columns create-user checks, as INFORMATION_SCHEMA reports them.
+static SCHEMA: [(&str, &str, &str, &str); 17] = [
+    ("User", "Id", "uniqueidentifier", "NO"),
+    ("User", "FirstName", "nvarchar", "NO"),
+    ("User", "LastName", "nvarchar", "NO"),
+    ("User", "Email", "nvarchar", "NO"),
+    ("User", "Username", "nvarchar", "NO"),
+    ("User", "Timezone", "nvarchar", "NO"),
+    ("User", "Locale", "nvarchar", "NO"),
+    ("User", "Active", "bit", "NO"),
+    ("User", "OfficeId", "uniqueidentifier", "YES"),
+    ("User", "SystemUser", "bit", "NO"),
+    ("User", "DepartmentId", "uniqueidentifier", "YES"),
+    ("User", "LastLogin", "datetime", "YES"),
+    ("User", "ActivationChanged", "datetime", "YES"),
+    ("UsersToRoles", "UserId", "uniqueidentifier", "NO"),
+    ("UsersToRoles", "UserRoleId", "uniqueidentifier", "NO"),
+    ("UserRole", "Id", "uniqueidentifier", "NO"),
+    ("UserRole", "Name", "nvarchar", "NO"),
+];
+
+fn schema(columns: &[(&str, &str, &str, &str)]) -> Reply {
+    let result_set = columns.iter().fold(
+        ResultSet::new(&["TABLE_NAME", "COLUMN_NAME", "DATA_TYPE", "IS_NULLABLE", ""]),
+        |result_set, (table, column, data_type, nullable)| {
+            result_set.row([
+                Value::from(*table),
+                Value::from(*column),
+                Value::from(*data_type),
+                Value::from(*nullable),
+                Value::from(0),
+            ])
+        },
+    );
+    Reply::Rows(result_set)
+}
+
+fn user_count(count: i32) -> Reply {
+    Reply::Rows(ResultSet::new(&[""]).row([count]))
+}
+
+/// A server with the expected schema and no users.
+fn server() -> FakeServer {
+    let server = FakeServer::start();
+    server.reply("INFORMATION_SCHEMA.COLUMNS", schema(&SCHEMA));
+    server.reply("SELECT COUNT(*)", user_count(0));
+    server
+}
+
+/// Runs tainer with an empty home directory, so no config or profile gets in the way.
+fn tainer(args: &[&str]) -> Output {
+    let home = std::env::temp_dir().join(format!("tainer-create-user-{}", std::process::id()));
+    std::fs::create_dir_all(&home).unwrap();
+    std::process::Command::new(env!("CARGO_BIN_EXE_tainer-cli"))
+        .args(args)
+        .env("HOME", &home)
+        .env_remove("USERPROFILE")
+        .current_dir(PathBuf::from(env!("CARGO_MANIFEST_DIR")))
+        .output()
+        .expect("Could not run tainer")
+}
+
+fn create_user(server: &FakeServer, extra: &[&str]) -> Output {
+    let connection_string = server.connection_string("Elos");
+    let mut args = vec![
+        "create-user",
+        "Jan van der Berg",
+        "jan@example.com",
+        "-c",
+        &connection_string,
+    ];
+    args.extend_from_slice(extra);
+    tainer(&args)
+}
+
+fn statements(server: &FakeServer) -> Vec<String> {
+    server
+        .requests()
+        .iter()
+        .map(|request| {
+            request
+                .sql()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+fn inserts_into<'a>(requests: &'a [Request], table: &str) -> Vec<&'a Request> {
+    let prefix = format!("INSERT INTO [dbo].[{}]", table);
+    requests
+        .iter()
+        .filter(|request| request.sql().starts_with(&prefix))
+        .collect()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn creates_the_user_in_one_transaction() {
+    let server = server();
+
+    let output = create_user(&server, &[]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let login = &server.logins()[0];
+    assert_eq!(login.user, "sa");
+    assert_eq!(login.database, "Elos");
+
+    let statements = statements(&server);
+    assert_eq!(statements.len(), 6, "{:#?}", statements);
+    assert_eq!(statements[0], "BEGIN TRANSACTION");
+    assert!(statements[1].contains("FROM INFORMATION_SCHEMA.COLUMNS WHERE [TABLE_SCHEMA] = 'dbo'"));
+    assert_eq!(
+        statements[2],
+        "SELECT COUNT(*) FROM [dbo].[User] WHERE [Email] = @P1"
+    );
+    assert!(statements[3].starts_with("INSERT INTO [dbo].[User] ([Id] ,[FirstName] ,[LastName]"));
+    assert_eq!(
+        statements[4],
+        "INSERT INTO [dbo].[UsersToRoles] ([UserId] ,[UserRoleId]) VALUES (@P1 ,@P2)"
+    );
+    assert_eq!(statements[5], "COMMIT TRANSACTION");
+}
+
+#[test]
+fn takes_the_connection_string_as_a_positional_argument() {
+    let server = server();
+    let connection_string = server.connection_string("Elos");
+
+    let output = tainer(&[
+        "create-user",
+        "Jan van der Berg",
+        "jan@example.com",
+        &connection_string,
+    ]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(server.logins()[0].database, "Elos");
+    assert!(inserts_into(&server.requests(), "User").len() == 1);
+}
+
+#[test]
+fn logs_in_with_the_sql_user_option() {
+    let server = server();
+
+    let output = create_user(
+        &server,
+        &["--sql-user", "tainer", "--sql-password", "secret"],
+    );
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(server.logins()[0].user, "tainer");
+}
+
+#[test]
+fn replaces_the_encryption_of_the_connection_string_with_the_encrypt_option() {
+    let server = server();
+
+    // the fake server can't do TLS, so requiring it has to fail before the login
+    let output = create_user(&server, &["--encrypt", "required"]);
+
+    assert!(!output.status.success());
+    assert!(server.logins().is_empty());
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn checks_the_ca_file_option_before_connecting() {
+    let server = server();
+
+    let output = create_user(&server, &["--ca-file", "missing.pem"]);
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("CA file missing.pem does not exist"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(server.logins().is_empty());
+}
+
+#[test]
+fn sends_the_user_as_parameters() {
+    let server = server();
+
+    let output = create_user(&server, &["--locale", "nl-NL", "--office-id", "7"]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let requests = server.requests();
+    assert_eq!(
+        requests[2].params(),
+        &[Value::from("jan@example.com")],
+        "the email is a parameter, never part of the statement"
+    );
+
+    let users = inserts_into(&requests, "User");
+    let params = users[0].params();
+    let Value::Guid(user_id) = &params[0] else {
+        panic!("Expected a GUID id, got {:?}", params[0]);
+    };
+    assert_eq!(
+        &params[1..],
+        &[
+            Value::from("Jan"),
+            Value::from("van der Berg"),
+            Value::from("jan@example.com"),
+            Value::from("jan@example.com"),
+            Value::from("W. Europe Standard Time"),
+            Value::from("nl-NL"),
+            Value::from("7"),
+            Value::Null,
+        ]
+    );
+
+    let roles = inserts_into(&requests, "UsersToRoles");
+    assert_eq!(
+        roles[0].params(),
+        &[
+            Value::from(user_id.as_str()),
+            Value::from(ALL_FEATURES_ROLE_ID)
+        ]
+    );
+}
+
+#[test]
+fn connects_the_user_to_the_roles_by_name() {
+    let server = server();
+    server.reply(
+        "FROM [dbo].[UserRole]",
+        Reply::Rows(
+            ResultSet::new(&["", "Name"])
+                .row(["0A6F3E4C-1B7D-4B4E-9C51-7A4D2B1E0F11", "Administrator"])
+                .row(["5C2B9D10-3E8F-4A67-8B21-C4D5E6F70812", "Reporting"])
+                .row([ALL_FEATURES_ROLE_ID, "ALL FEATURES"]),
+        ),
+    );
+
+    let output = create_user(&server, &["--role", "administrator", "--role", "Reporting"]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let requests = server.requests();
+    let role_ids = inserts_into(&requests, "UsersToRoles")
+        .iter()
+        .map(|request| request.params()[1].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        role_ids,
+        vec![
+            Value::from("0A6F3E4C-1B7D-4B4E-9C51-7A4D2B1E0F11"),
+            Value::from("5C2B9D10-3E8F-4A67-8B21-C4D5E6F70812"),
+        ]
+    );
+}
+
+#[test]
+fn unknown_role_rolls_back_before_inserting() {
+    let server = server();
+    server.reply(
+        "FROM [dbo].[UserRole]",
+        Reply::Rows(ResultSet::new(&["", "Name"]).row([ALL_FEATURES_ROLE_ID, "ALL FEATURES"])),
+    );
+
+    let output = create_user(&server, &["--role", "Administrator"]);
+
+    assert!(!output.status.success());
+    let statements = statements(&server);
+    assert!(!statements.iter().any(|sql| sql.starts_with("INSERT")));
+    assert_eq!(statements.last().unwrap(), "ROLLBACK TRANSACTION");
+}
+
+#[test]
+fn existing_email_rolls_back_before_inserting() {
+    let server = FakeServer::start();
+    server.reply("INFORMATION_SCHEMA.COLUMNS", schema(&SCHEMA));
+    server.reply("SELECT COUNT(*)", user_count(1));
+
+    let output = create_user(&server, &[]);
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("User with email jan@example.com already exists"),
+        "{}",
+        stderr(&output)
+    );
+    let statements = statements(&server);
+    assert!(!statements.iter().any(|sql| sql.starts_with("INSERT")));
+    assert_eq!(statements.last().unwrap(), "ROLLBACK TRANSACTION");
+}
+
+#[test]
+fn schema_mismatch_stops_before_reading_users() {
+    let server = FakeServer::start();
+    let without_locale = SCHEMA
+        .iter()
+        .filter(|(table, column, _, _)| !(*table == "User" && *column == "Locale"))
+        .copied()
+        .collect::<Vec<_>>();
+    server.reply("INFORMATION_SCHEMA.COLUMNS", schema(&without_locale));
+
+    let output = create_user(&server, &[]);
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("[dbo].[User] has no column [Locale]"),
+        "{}",
+        stderr(&output)
+    );
+    let statements = statements(&server);
+    assert_eq!(statements.len(), 3, "{:#?}", statements);
+    assert_eq!(statements[2], "ROLLBACK TRANSACTION");
+}
+
+#[test]
+fn not_null_office_column_is_a_mismatch() {
+    let server = FakeServer::start();
+    let office_required = SCHEMA
+        .iter()
+        .map(
+            |&(table, column, data_type, nullable)| match (table, column) {
+                ("User", "OfficeId") => (table, column, data_type, "NO"),
+                _ => (table, column, data_type, nullable),
+            },
+        )
+        .collect::<Vec<_>>();
+    server.reply("INFORMATION_SCHEMA.COLUMNS", schema(&office_required));
+
+    let output = create_user(&server, &[]);
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("[dbo].[User].[OfficeId] does not allow NULL"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn failed_role_insert_rolls_back_the_user() {
+    let server = server();
+    server.reply(
+        "INSERT INTO [dbo].[UsersToRoles]",
+        Reply::Error {
+            code: 547,
+            message: "The INSERT statement conflicted with the FOREIGN KEY constraint".to_string(),
+        },
+    );
+
+    let output = create_user(&server, &[]);
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("FOREIGN KEY constraint"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(statements(&server).last().unwrap(), "ROLLBACK TRANSACTION");
+}